@@ -96,5 +96,61 @@ fn bench_binary_parsing(c: &mut Criterion) {
     group.finish();
 }
 
-criterion_group!(benches, bench_ascii_parsing, bench_binary_parsing);
+fn bench_binary_loading(c: &mut Criterion) {
+    let triangle_counts = vec![10000, 100000, 1000000];
+
+    let mut group = c.benchmark_group("binary_stl_loading");
+
+    for &count in &triangle_counts {
+        let triangles: Vec<Triangle> = (0..count)
+            .map(|i| Triangle {
+                vertices: [
+                    Vec3::new(i as f32 * 0.1, 0.0, 0.0),
+                    Vec3::new(i as f32 * 0.1, 1.0, 0.0),
+                    Vec3::new(i as f32 * 0.1, 0.0, 1.0),
+                ],
+                normal: Vec3::new(0.0, 0.0, 1.0),
+            })
+            .collect();
+
+        let path = std::env::temp_dir().join(format!("cadstream_bench_{}.stl", count));
+        std::fs::write(&path, create_binary_stl_data(&triangles)).unwrap();
+
+        // Read the whole file into memory, then parse the buffer
+        group.bench_with_input(
+            BenchmarkId::new("read_to_memory", count),
+            &path,
+            |b, path| {
+                let parser = StlParser::new();
+                b.iter(|| {
+                    let data = std::fs::read(path).unwrap();
+                    parser
+                        .parse_data(&data, format!("test_{}.stl", count))
+                        .unwrap()
+                });
+            },
+        );
+
+        // Memory mapped, zero-copy records converted in parallel
+        group.bench_with_input(
+            BenchmarkId::new("mmap_parallel", count),
+            &path,
+            |b, path| {
+                let parser = StlParser::new();
+                b.iter(|| parser.parse_file_mmap(path).unwrap());
+            },
+        );
+
+        std::fs::remove_file(&path).ok();
+    }
+
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_ascii_parsing,
+    bench_binary_parsing,
+    bench_binary_loading
+);
 criterion_main!(benches);
@@ -0,0 +1,242 @@
+use flate2::read::GzDecoder;
+use std::io::{self, Cursor, Read};
+use std::path::{Path, PathBuf};
+use zip::ZipArchive;
+
+use crate::error::{CadStreamError, Result};
+
+/// Extensions of wrappers that are unpacked before parsing
+pub const ARCHIVE_EXTENSIONS: &[&str] = &["gz", "gzip", "zst", "zstd", "zip"];
+
+/// Default for how large a compressed file or archive may unpack to, 1 GiB
+pub const DEFAULT_MAX_DECOMPRESSED_SIZE: u64 = 1 << 30;
+
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+
+/// Compression wrapped around a single file, such as `part.stl.gz`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// Identify the compression from its magic number
+    pub fn detect(bytes: &[u8]) -> Option<Self> {
+        if bytes.starts_with(&[0x1f, 0x8b]) {
+            Some(Compression::Gzip)
+        } else if bytes.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Compression::Zstd)
+        } else {
+            None
+        }
+    }
+
+    pub fn extensions(&self) -> &'static [&'static str] {
+        match self {
+            Compression::Gzip => &["gz", "gzip"],
+            Compression::Zstd => &["zst", "zstd"],
+        }
+    }
+
+    /// Decompress `bytes`, failing if the output is larger than `limit` bytes
+    pub fn decompress(&self, bytes: &[u8], limit: u64) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        // One byte past the limit tells a file of exactly `limit` bytes from a larger one
+        let read_limit = limit.saturating_add(1);
+        let result = match self {
+            Compression::Gzip => GzDecoder::new(bytes)
+                .take(read_limit)
+                .read_to_end(&mut data),
+            Compression::Zstd => zstd::stream::read::Decoder::new(bytes)
+                .and_then(|d| d.take(read_limit).read_to_end(&mut data)),
+        };
+
+        match result {
+            Ok(_) => {
+                check_size(data.len() as u64, limit, || format!("{:?} data", self))?;
+                Ok(data)
+            }
+            Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => Err(error.into()),
+            Err(error) => Err(CadStreamError::malformed(format!(
+                "Invalid {:?} data: {}",
+                self, error
+            ))),
+        }
+    }
+}
+
+/// Unwrap gzip/zstd compressed data of at most `limit` bytes, dropping the compression
+/// extension from `file_name` so `part.stl.gz` is parsed as `part.stl`
+pub fn decompress(data: Vec<u8>, file_name: String, limit: u64) -> Result<(Vec<u8>, String)> {
+    let Some(compression) = Compression::detect(&data) else {
+        return Ok((data, file_name));
+    };
+
+    let data = compression.decompress(&data, limit)?;
+    let file_name = match file_name.rsplit_once('.') {
+        Some((stem, extension))
+            if compression
+                .extensions()
+                .contains(&extension.to_lowercase().as_str()) =>
+        {
+            stem.to_string()
+        }
+        _ => file_name,
+    };
+    Ok((data, file_name))
+}
+
+pub fn is_zip(bytes: &[u8]) -> bool {
+    bytes.starts_with(ZIP_MAGIC)
+}
+
+/// A file inside a zip archive
+#[derive(Debug)]
+pub struct ArchiveEntry {
+    /// Path relative to the archive root
+    pub path: PathBuf,
+    /// Contents, already unwrapped if the entry itself is compressed
+    pub data: Vec<u8>,
+    pub file_name: String,
+}
+
+/// Read every entry of a zip archive whose path passes `is_supported`
+///
+/// Together the entries may unpack to at most `limit` bytes.
+pub fn zip_entries(
+    bytes: &[u8],
+    limit: u64,
+    is_supported: impl Fn(&Path) -> bool,
+) -> Result<Vec<ArchiveEntry>> {
+    let mut archive = ZipArchive::new(Cursor::new(bytes))?;
+    let mut entries = Vec::new();
+    let mut remaining = limit;
+
+    for index in 0..archive.len() {
+        let mut file = archive.by_index(index)?;
+        // Entries escaping the archive root can't be keyed below it
+        let Some(path) = file.enclosed_name() else {
+            continue;
+        };
+        if file.is_dir() || !is_supported(&path) || is_metadata(&path) {
+            continue;
+        }
+
+        // The declared size is checked first, but only the bytes read can be trusted
+        let too_large = || format!("{} in the zip archive", path.display());
+        check_size(file.size(), remaining, too_large)?;
+        let mut data = Vec::new();
+        (&mut file)
+            .take(remaining.saturating_add(1))
+            .read_to_end(&mut data)
+            .map_err(|error| CadStreamError::io(&path, error))?;
+        check_size(data.len() as u64, remaining, too_large)?;
+
+        let file_name = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or("unknown")
+            .to_string();
+        let (data, file_name) = decompress(data, file_name, remaining)?;
+        remaining -= data.len() as u64;
+        entries.push(ArchiveEntry {
+            path,
+            data,
+            file_name,
+        });
+    }
+
+    Ok(entries)
+}
+
+fn check_size(size: u64, limit: u64, what: impl FnOnce() -> String) -> Result<()> {
+    if size > limit {
+        return Err(CadStreamError::malformed(format!(
+            "{} unpacks to more than {} bytes",
+            what(),
+            limit
+        )));
+    }
+    Ok(())
+}
+
+/// Resource forks and similar files added by archivers
+fn is_metadata(path: &Path) -> bool {
+    path.starts_with("__MACOSX")
+        || path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .is_some_and(|name| name.starts_with("._"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsers::FileFormat;
+    use flate2::write::GzEncoder;
+    use std::io::Write;
+    use zip::write::SimpleFileOptions;
+
+    #[test]
+    fn test_decompress_and_list_zip_entries() {
+        let stl = b"solid a\nendsolid a\n".to_vec();
+
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&stl).unwrap();
+        let gzip = encoder.finish().unwrap();
+        let limit = DEFAULT_MAX_DECOMPRESSED_SIZE;
+        let (data, name) = decompress(gzip.clone(), "part.STL.GZ".to_string(), limit).unwrap();
+        assert_eq!((data, name.as_str()), (stl.clone(), "part.STL"));
+
+        let zstd = zstd::encode_all(stl.as_slice(), 0).unwrap();
+        assert_eq!(decompress(zstd, "part".to_string(), limit).unwrap().0, stl);
+        assert!(decompress(gzip[..gzip.len() - 12].to_vec(), "x.gz".to_string(), limit).is_err());
+
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, data) in [
+            ("parts/a.stl", stl.as_slice()),
+            ("parts/b.stl.gz", gzip.as_slice()),
+            ("readme.txt", b"not a part".as_slice()),
+            ("__MACOSX/parts/._a.stl", b"".as_slice()),
+        ] {
+            writer
+                .start_file(name, SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(data).unwrap();
+        }
+        let zip = writer.finish().unwrap().into_inner();
+
+        assert!(is_zip(&zip));
+        let supported = |path: &Path| FileFormat::from_path(path).is_some();
+        let entries = zip_entries(&zip, limit, supported).unwrap();
+        let names: Vec<_> = entries.iter().map(|e| e.path.clone()).collect();
+        assert_eq!(
+            names,
+            [
+                PathBuf::from("parts/a.stl"),
+                PathBuf::from("parts/b.stl.gz")
+            ]
+        );
+        assert_eq!(entries[1].file_name, "b.stl");
+        assert_eq!(entries[1].data, stl);
+
+        // Output is capped, not just the compressed size
+        let len = stl.len() as u64;
+        assert!(decompress(gzip.clone(), "x.gz".to_string(), len).is_ok());
+        assert!(decompress(gzip, "x.gz".to_string(), len - 1).is_err());
+        // Two entries of `len` bytes each
+        assert!(zip_entries(&zip, len, supported).is_err());
+    }
+
+    #[test]
+    fn test_decompression_bomb_is_rejected() {
+        let zeros = vec![0u8; 1 << 20];
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::best());
+        encoder.write_all(&zeros).unwrap();
+        let gzip = encoder.finish().unwrap();
+
+        let error = decompress(gzip, "bomb.stl.gz".to_string(), 1024).unwrap_err();
+        assert!(matches!(error, CadStreamError::Malformed { .. }));
+    }
+}
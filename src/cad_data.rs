@@ -1,4 +1,10 @@
 use glam::Vec3;
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::ops::Range;
+
+/// Default tolerance used when welding vertices into an [`IndexedMesh`]
+pub const DEFAULT_WELD_EPSILON: f32 = 1e-6;
 
 #[derive(Debug, Clone)]
 pub struct Triangle {
@@ -6,12 +12,99 @@ pub struct Triangle {
     pub normal: Vec3,
 }
 
+/// RGBA color with 8 bits per channel
+pub type Rgba = [u8; 4];
+
+/// Color for models, or facets, that don't carry their own
+pub const DEFAULT_COLOR: Rgba = [178, 178, 230, 255];
+
+/// A named group of consecutive triangles within a model, such as an OBJ `o`/`g` group
+/// or one `solid` of a multi-solid ASCII STL
+#[derive(Debug, Clone, PartialEq)]
+pub struct MeshPart {
+    pub name: String,
+    pub triangles: Range<usize>,
+}
+
 #[derive(Debug, Clone)]
 pub struct CadModel {
     pub name: String,
     pub triangles: Vec<Triangle>,
+    pub parts: Vec<MeshPart>,
+    /// Optional per-vertex colors, one entry per triangle corner
+    pub vertex_colors: Option<Vec<[Rgba; 3]>>,
+    /// Shared-vertex form of `triangles` kept by parsers of indexed formats, face `i`
+    /// being triangle `i`, so vertices aren't welded again for rendering
+    pub indexed: Option<IndexedMesh>,
     pub bounds: BoundingBox,
     pub precision_info: PrecisionInfo,
+    /// Hash of the geometry, parts and colors, see [`CadModel::compute_content_hash`]
+    pub content_hash: u64,
+}
+
+/// Indexed triangle mesh: a buffer of unique vertices plus faces referencing them
+#[derive(Debug, Clone, Default)]
+pub struct IndexedMesh {
+    pub positions: Vec<Vec3>,
+    /// Optional per-vertex colors, parallel to `positions`
+    pub colors: Option<Vec<Rgba>>,
+    pub faces: Vec<[u32; 3]>,
+    /// One normal per face
+    pub normals: Vec<Vec3>,
+}
+
+impl IndexedMesh {
+    /// The faces in `range` with only the vertices they use
+    pub fn subset(&self, range: Range<usize>) -> IndexedMesh {
+        let mut remap: HashMap<u32, u32> = HashMap::new();
+        let mut subset = IndexedMesh {
+            colors: self.colors.as_ref().map(|_| Vec::new()),
+            normals: self.normals[range.clone()].to_vec(),
+            ..Default::default()
+        };
+
+        for face in &self.faces[range] {
+            let face = face.map(|index| {
+                *remap.entry(index).or_insert_with(|| {
+                    subset.positions.push(self.positions[index as usize]);
+                    if let (Some(colors), Some(source)) = (subset.colors.as_mut(), &self.colors) {
+                        colors.push(source[index as usize]);
+                    }
+                    (subset.positions.len() - 1) as u32
+                })
+            });
+            subset.faces.push(face);
+        }
+        subset
+    }
+
+    /// Add the vertices and faces of `other`, filling in [`DEFAULT_COLOR`] when only
+    /// one of the meshes is colored
+    pub fn append(&mut self, other: &IndexedMesh) {
+        let offset = self.positions.len() as u32;
+
+        match (&mut self.colors, &other.colors) {
+            (Some(colors), Some(other_colors)) => colors.extend_from_slice(other_colors),
+            (Some(colors), None) => {
+                colors.resize(offset as usize + other.positions.len(), DEFAULT_COLOR)
+            }
+            (None, Some(other_colors)) => {
+                let mut colors = vec![DEFAULT_COLOR; offset as usize];
+                colors.extend_from_slice(other_colors);
+                self.colors = Some(colors);
+            }
+            (None, None) => {}
+        }
+
+        self.positions.extend_from_slice(&other.positions);
+        self.faces.extend(
+            other
+                .faces
+                .iter()
+                .map(|face| face.map(|index| index + offset)),
+        );
+        self.normals.extend_from_slice(&other.normals);
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -41,8 +134,218 @@ impl CadModel {
         Self {
             name,
             triangles,
+            parts: Vec::new(),
+            vertex_colors: None,
+            indexed: None,
             bounds,
             precision_info,
+            content_hash: 0, // Set by the file watcher once the model is complete
+        }
+    }
+
+    /// Hash everything that affects how the model looks, so a re-saved file with
+    /// identical content can be told apart from a real edit
+    pub fn compute_content_hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+
+        self.triangles.len().hash(&mut hasher);
+        for triangle in &self.triangles {
+            for vertex in &triangle.vertices {
+                vertex.to_array().map(f32::to_bits).hash(&mut hasher);
+            }
+            triangle
+                .normal
+                .to_array()
+                .map(f32::to_bits)
+                .hash(&mut hasher);
+        }
+
+        for part in &self.parts {
+            part.name.hash(&mut hasher);
+            part.triangles.hash(&mut hasher);
+        }
+
+        self.vertex_colors.hash(&mut hasher);
+
+        hasher.finish()
+    }
+
+    /// Combine several models into one, each becoming a part named after it, or
+    /// keeping its own parts if it has any
+    pub fn merge(name: String, models: Vec<CadModel>) -> Self {
+        let any_colors = models.iter().any(|model| model.vertex_colors.is_some());
+        let mut indexed = models
+            .iter()
+            .all(|model| model.indexed.is_some())
+            .then(IndexedMesh::default);
+        let mut triangles = Vec::new();
+        let mut parts = Vec::new();
+        let mut colors = Vec::new();
+        let mut vertex_count = 0;
+        let mut file_size_bytes = 0;
+
+        for model in models {
+            let offset = triangles.len();
+            if model.parts.is_empty() {
+                parts.push(MeshPart {
+                    name: model.name,
+                    triangles: offset..offset + model.triangles.len(),
+                });
+            } else {
+                parts.extend(model.parts.into_iter().map(|part| MeshPart {
+                    name: part.name,
+                    triangles: part.triangles.start + offset..part.triangles.end + offset,
+                }));
+            }
+
+            if any_colors {
+                match model.vertex_colors {
+                    Some(model_colors) => colors.extend(model_colors),
+                    None => colors.resize(offset + model.triangles.len(), [DEFAULT_COLOR; 3]),
+                }
+            }
+            if let (Some(indexed), Some(model_indexed)) = (indexed.as_mut(), &model.indexed) {
+                indexed.append(model_indexed);
+            }
+            vertex_count += model.precision_info.vertex_count;
+            file_size_bytes += model.precision_info.file_size_bytes;
+            triangles.extend(model.triangles);
+        }
+
+        let mut merged = Self::new(name, triangles);
+        merged.parts = parts;
+        merged.vertex_colors = any_colors.then_some(colors);
+        merged.indexed = indexed;
+        merged.precision_info.vertex_count = vertex_count;
+        merged.precision_info.file_size_bytes = file_size_bytes;
+        merged
+    }
+
+    /// The parts in triangle order, plus unnamed parts for triangles outside every
+    /// part, so that writers emit the whole model. A model without parts is one
+    /// unnamed part.
+    pub fn covering_parts(&self) -> Vec<MeshPart> {
+        let mut covered = vec![false; self.triangles.len()];
+        for part in &self.parts {
+            covered[part.triangles.clone()].fill(true);
+        }
+
+        let mut parts = self.parts.clone();
+        let mut index = 0;
+        while let Some(start) = (index..covered.len()).find(|&i| !covered[i]) {
+            let end = (start..covered.len())
+                .find(|&i| covered[i])
+                .unwrap_or(covered.len());
+            parts.push(MeshPart {
+                name: String::new(),
+                triangles: start..end,
+            });
+            index = end;
+        }
+
+        if parts.is_empty() {
+            parts.push(MeshPart {
+                name: String::new(),
+                triangles: 0..0,
+            });
+        }
+        parts.sort_by_key(|part| part.triangles.start);
+        parts
+    }
+
+    /// Build a model from an indexed mesh, expanding faces into triangles and keeping
+    /// the mesh as [`CadModel::indexed`]
+    pub fn from_indexed(name: String, mesh: IndexedMesh) -> Self {
+        let triangles = mesh
+            .faces
+            .iter()
+            .zip(&mesh.normals)
+            .map(|(face, &normal)| Triangle {
+                vertices: face.map(|i| mesh.positions[i as usize]),
+                normal,
+            })
+            .collect();
+
+        let mut model = Self::new(name, triangles);
+        model.vertex_colors = mesh.colors.as_ref().map(|colors| {
+            mesh.faces
+                .iter()
+                .map(|face| face.map(|i| colors[i as usize]))
+                .collect()
+        });
+        model.precision_info.vertex_count = mesh.positions.len();
+        model.indexed = Some(mesh);
+
+        model
+    }
+
+    /// The indexed form of the triangles in `range` for rendering, reusing
+    /// [`CadModel::indexed`] when the parser kept one and otherwise welding with
+    /// [`DEFAULT_WELD_EPSILON`]
+    pub fn indexed_range(&self, range: Range<usize>) -> IndexedMesh {
+        match &self.indexed {
+            Some(mesh) if range == (0..mesh.faces.len()) => mesh.clone(),
+            Some(mesh) => mesh.subset(range),
+            None => self.to_indexed_range(range, DEFAULT_WELD_EPSILON),
+        }
+    }
+
+    /// Weld the triangle soup into an indexed mesh
+    ///
+    /// Vertices falling into the same `epsilon`-sized grid cell (and sharing the same
+    /// color, if any) are merged. An `epsilon` of zero only merges exact duplicates.
+    pub fn to_indexed(&self, epsilon: f32) -> IndexedMesh {
+        self.to_indexed_range(0..self.triangles.len(), epsilon)
+    }
+
+    /// Like [`CadModel::to_indexed`] for a subset of the triangles, such as one part
+    pub fn to_indexed_range(&self, range: Range<usize>, epsilon: f32) -> IndexedMesh {
+        let mut positions = Vec::new();
+        let mut colors = self.vertex_colors.as_ref().map(|_| Vec::new());
+        let mut faces = Vec::with_capacity(range.len());
+        let mut lookup: HashMap<([i64; 3], Rgba), u32> = HashMap::new();
+
+        for (triangle_index, triangle) in self.triangles[range.clone()]
+            .iter()
+            .enumerate()
+            .map(|(i, t)| (range.start + i, t))
+        {
+            let mut face = [0u32; 3];
+
+            for (corner, vertex) in triangle.vertices.iter().enumerate() {
+                let color = self
+                    .vertex_colors
+                    .as_ref()
+                    .map_or([0; 4], |c| c[triangle_index][corner]);
+
+                face[corner] = *lookup
+                    .entry((Self::weld_key(*vertex, epsilon), color))
+                    .or_insert_with(|| {
+                        positions.push(*vertex);
+                        if let Some(colors) = colors.as_mut() {
+                            colors.push(color);
+                        }
+                        (positions.len() - 1) as u32
+                    });
+            }
+
+            faces.push(face);
+        }
+
+        IndexedMesh {
+            positions,
+            colors,
+            faces,
+            normals: self.triangles[range].iter().map(|t| t.normal).collect(),
+        }
+    }
+
+    fn weld_key(vertex: Vec3, epsilon: f32) -> [i64; 3] {
+        if epsilon > 0.0 {
+            (vertex / epsilon).round().to_array().map(|c| c as i64)
+        } else {
+            // Adding 0.0 folds -0.0 into 0.0 so both weld together
+            (vertex + Vec3::ZERO).to_array().map(|c| c.to_bits() as i64)
         }
     }
 
@@ -80,3 +383,72 @@ impl CadModel {
         size.x.max(size.y).max(size.z)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_indexed_welds_shared_vertices() {
+        let normal = Vec3::Z;
+        let triangles = vec![
+            Triangle {
+                vertices: [Vec3::ZERO, Vec3::X, Vec3::new(1.0, 1.0, 0.0)],
+                normal,
+            },
+            Triangle {
+                vertices: [Vec3::new(0.0, 0.0, 1e-8), Vec3::new(1.0, 1.0, 0.0), Vec3::Y],
+                normal,
+            },
+        ];
+        let model = CadModel::new("quad".to_string(), triangles);
+
+        let welded = model.to_indexed(DEFAULT_WELD_EPSILON);
+        assert_eq!(welded.positions.len(), 4);
+        assert_eq!(welded.faces, vec![[0, 1, 2], [0, 2, 3]]);
+
+        let exact = model.to_indexed(0.0);
+        assert_eq!(exact.positions.len(), 5);
+
+        let roundtrip = CadModel::from_indexed("quad".to_string(), welded);
+        assert_eq!(roundtrip.triangles.len(), 2);
+        assert_eq!(roundtrip.triangles[1].vertices[2], Vec3::Y);
+        assert_eq!(roundtrip.precision_info.vertex_count, 4);
+
+        // The kept mesh is reused for rendering, a part only gets the vertices it uses
+        let part = roundtrip.indexed_range(1..2);
+        assert_eq!(
+            part.positions,
+            [Vec3::ZERO, Vec3::new(1.0, 1.0, 0.0), Vec3::Y]
+        );
+        assert_eq!(part.faces, vec![[0, 1, 2]]);
+
+        let merged = CadModel::merge("both".to_string(), vec![roundtrip.clone(), roundtrip]);
+        let indexed = merged.indexed.as_ref().unwrap();
+        assert_eq!(indexed.positions.len(), 8);
+        assert_eq!(indexed.faces[3], [4, 6, 7]);
+    }
+
+    #[test]
+    fn test_content_hash_detects_same_count_edits() {
+        let triangle = Triangle {
+            vertices: [Vec3::ZERO, Vec3::X, Vec3::Y],
+            normal: Vec3::Z,
+        };
+        let original = CadModel::new("part".to_string(), vec![triangle.clone()]);
+        let reloaded = CadModel::new("part".to_string(), vec![triangle.clone()]);
+
+        let mut moved = triangle;
+        moved.vertices[2] = Vec3::new(0.0, 2.0, 0.0);
+        let edited = CadModel::new("part".to_string(), vec![moved]);
+
+        assert_eq!(
+            original.compute_content_hash(),
+            reloaded.compute_content_hash()
+        );
+        assert_ne!(
+            original.compute_content_hash(),
+            edited.compute_content_hash()
+        );
+    }
+}
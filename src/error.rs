@@ -0,0 +1,142 @@
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use thiserror::Error;
+
+use crate::parsers::SourceLocation;
+
+/// Errors returned by the parsers, the parser factory, the writers and the file watcher
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum CadStreamError {
+    /// Reading a file or stream failed
+    #[error("{}", describe_io(.path))]
+    Io {
+        path: Option<PathBuf>,
+        #[source]
+        source: io::Error,
+    },
+    /// No parser is known for the file
+    #[error("Unsupported file format: {0}")]
+    UnsupportedFormat(String),
+    /// A parser for the format exists but was not compiled in
+    #[error("{0}")]
+    ParserUnavailable(String),
+    /// The model can't be written in the requested format
+    #[error("{0}")]
+    Export(String),
+    /// The data ends before everything it declares was read
+    #[error("{}Data truncated: {message}", Located(.location))]
+    Truncated {
+        location: Option<SourceLocation>,
+        message: String,
+    },
+    /// A numeric field could not be parsed
+    #[error("{}Invalid number '{token}'", Located(.location))]
+    InvalidNumber {
+        location: Option<SourceLocation>,
+        token: String,
+    },
+    /// Any other structural problem in the data
+    #[error("{}{message}", Located(.location))]
+    Malformed {
+        location: Option<SourceLocation>,
+        message: String,
+    },
+    /// A watch include/exclude pattern is not a valid glob
+    #[error("Invalid glob pattern: {pattern}")]
+    InvalidPattern {
+        pattern: String,
+        #[source]
+        source: globset::Error,
+    },
+    /// A zip archive could not be read
+    #[error("Invalid zip archive")]
+    Archive(#[from] zip::result::ZipError),
+    /// Loading one entry of a zip archive failed
+    #[error("{}: {error}", .entry.display())]
+    ArchiveEntry {
+        entry: PathBuf,
+        error: Box<CadStreamError>,
+    },
+    /// The platform file watcher failed
+    #[error("File watcher error")]
+    Watch(#[from] notify::Error),
+}
+
+pub type Result<T, E = CadStreamError> = std::result::Result<T, E>;
+
+impl CadStreamError {
+    pub(crate) fn malformed(message: impl Into<String>) -> Self {
+        CadStreamError::Malformed {
+            location: None,
+            message: message.into(),
+        }
+    }
+
+    pub(crate) fn truncated(message: impl Into<String>) -> Self {
+        CadStreamError::Truncated {
+            location: None,
+            message: message.into(),
+        }
+    }
+
+    /// Wrap an I/O error on `path`
+    pub(crate) fn io(path: impl Into<PathBuf>, source: io::Error) -> Self {
+        CadStreamError::Io {
+            path: Some(path.into()),
+            source,
+        }
+    }
+
+    /// Set where in the input the error was found
+    pub(crate) fn at(mut self, at: SourceLocation) -> Self {
+        if let CadStreamError::Truncated { location, .. }
+        | CadStreamError::InvalidNumber { location, .. }
+        | CadStreamError::Malformed { location, .. } = &mut self
+        {
+            *location = Some(at);
+        }
+        self
+    }
+
+    /// Where in the input the error was found, if known
+    pub fn location(&self) -> Option<SourceLocation> {
+        match self {
+            CadStreamError::Truncated { location, .. }
+            | CadStreamError::InvalidNumber { location, .. }
+            | CadStreamError::Malformed { location, .. } => *location,
+            _ => None,
+        }
+    }
+}
+
+/// Running out of data while reading is truncation, anything else a real I/O error
+impl From<io::Error> for CadStreamError {
+    fn from(source: io::Error) -> Self {
+        if source.kind() == io::ErrorKind::UnexpectedEof {
+            CadStreamError::truncated(source.to_string())
+        } else {
+            CadStreamError::Io { path: None, source }
+        }
+    }
+}
+
+fn describe_io(path: &Option<PathBuf>) -> String {
+    match path {
+        Some(path) => format!("I/O error on {}", path.display()),
+        None => "I/O error".to_string(),
+    }
+}
+
+/// Prefixes a message with its location when there is one
+struct Located<'a>(&'a Option<SourceLocation>);
+
+impl fmt::Display for Located<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(location) => write!(f, "{}: ", location),
+            None => Ok(()),
+        }
+    }
+}
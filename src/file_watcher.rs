@@ -1,52 +1,96 @@
-use anyhow::Result;
 use notify::{
-    event::CreateKind, event::ModifyKind, Event, EventKind, RecommendedWatcher, RecursiveMode,
-    Watcher,
+    event::{CreateKind, ModifyKind, RemoveKind, RenameMode},
+    Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher,
 };
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::time::Duration;
-use tokio::sync::{mpsc, RwLock};
+use std::time::SystemTime;
+use tokio::sync::{broadcast, mpsc};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
 
-use crate::cad_data::CadModel;
-use crate::parsers::{parse_file, FileFormat, ParserFactory};
+use crate::error::{CadStreamError, Result};
+use crate::model_events::{ModelEvent, ModelRegistry};
+use crate::parsers::{load_data, read_file, FileFormat, LoadedModel, ParseOptions};
+use crate::watch_config::{WatchConfig, WatchFilter};
+
+/// A change on disk the processing task has to apply to the registry
+#[derive(Debug, Clone, PartialEq)]
+enum FileChange {
+    /// File was created or written, (re)load it
+    Load(PathBuf),
+    /// File was deleted or moved away, drop its model
+    Unload(PathBuf),
+    /// File was renamed, move its model to the new path
+    Rename { from: PathBuf, to: PathBuf },
+}
+
+/// Size and modification time of a file, used to decide whether it's still being written
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct FileStamp {
+    len: u64,
+    modified: Option<SystemTime>,
+}
+
+impl FileStamp {
+    async fn read(path: &Path) -> Result<Self> {
+        let metadata = tokio::fs::metadata(path)
+            .await
+            .map_err(|error| CadStreamError::io(path, error))?;
+        Ok(Self {
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+        })
+    }
+}
+
+/// State shared by the notify callback, the processing task and the load tasks
+#[derive(Clone)]
+struct WatchContext {
+    models: ModelRegistry,
+    events: broadcast::Sender<ModelEvent>,
+    config: Arc<WatchConfig>,
+    filter: Arc<WatchFilter>,
+}
 
 pub struct FileWatcher {
     _watcher: RecommendedWatcher,
-    current_model: Arc<RwLock<Option<CadModel>>>,
+    context: WatchContext,
 }
 
 impl FileWatcher {
-    pub async fn new(current_model: Arc<RwLock<Option<CadModel>>>) -> Result<Self> {
-        let (tx, mut rx) = mpsc::channel::<PathBuf>(32);
-        let current_model_clone = current_model.clone();
-
-        // Start file processing task
-        tokio::spawn(async move {
-            while let Some(path) = rx.recv().await {
-                if let Err(e) = Self::process_file(&path, &current_model_clone).await {
-                    eprintln!("❌ Failed to process file {}: {}", path.display(), e);
-                }
-            }
-        });
+    /// Watch the working directory, keep every loaded model in `models` and publish
+    /// changes on `events`
+    pub async fn new(models: ModelRegistry, events: broadcast::Sender<ModelEvent>) -> Result<Self> {
+        Self::with_config(models, events, WatchConfig::default()).await
+    }
+
+    /// Like [`FileWatcher::new`] with custom roots, filters and write-completion detection
+    pub async fn with_config(
+        models: ModelRegistry,
+        events: broadcast::Sender<ModelEvent>,
+        config: WatchConfig,
+    ) -> Result<Self> {
+        let context = WatchContext {
+            models,
+            events,
+            filter: Arc::new(WatchFilter::new(&config)?),
+            config: Arc::new(config),
+        };
 
+        // Unbounded so bursts of write events never get dropped, they're coalesced below
+        let (tx, rx) = mpsc::unbounded_channel::<FileChange>();
+
+        tokio::spawn(Self::process_changes(rx, context.clone()));
+
+        let filter = context.filter.clone();
         let watcher =
             notify::recommended_watcher(move |res: Result<Event, notify::Error>| match res {
                 Ok(event) => {
-                    let should_process = match event.kind {
-                        EventKind::Create(CreateKind::File) => true,
-                        EventKind::Modify(ModifyKind::Data(_)) => true,
-                        _ => false,
-                    };
-
-                    if should_process {
-                        for path in event.paths {
-                            if Self::is_supported_file(&path) {
-                                println!("📁 Detected CAD file: {}", path.display());
-                                if let Err(e) = tx.try_send(path.to_path_buf()) {
-                                    eprintln!("Failed to queue file for processing: {}", e);
-                                }
-                            }
+                    for change in Self::classify_event(event, &filter) {
+                        if let Err(e) = tx.send(change) {
+                            eprintln!("Failed to queue file for processing: {}", e);
                         }
                     }
                 }
@@ -55,7 +99,7 @@ impl FileWatcher {
 
         let mut file_watcher = Self {
             _watcher: watcher,
-            current_model,
+            context,
         };
 
         file_watcher.start_watching().await?;
@@ -63,56 +107,412 @@ impl FileWatcher {
         Ok(file_watcher)
     }
 
+    /// Subscribe to model updates published by this watcher
+    pub fn subscribe(&self) -> broadcast::Receiver<ModelEvent> {
+        self.context.events.subscribe()
+    }
+
     async fn start_watching(&mut self) -> Result<()> {
-        let current_dir = std::env::current_dir()?;
-        println!("👀 Watching directory: {}", current_dir.display());
-
-        self._watcher
-            .watch(&current_dir, RecursiveMode::NonRecursive)?;
-
-        // Check for existing CAD files in the directory
-        let mut entries = tokio::fs::read_dir(&current_dir).await?;
-
-        while let Some(entry) = entries.next_entry().await? {
-            let path = entry.path();
-            if Self::is_supported_file(&path) {
-                println!("📄 Found existing CAD file: {}", path.display());
-                if let Err(e) = Self::process_file(&path, &self.current_model).await {
-                    eprintln!(
-                        "❌ Failed to process existing file {}: {}",
+        let mode = if self.context.config.recursive {
+            RecursiveMode::Recursive
+        } else {
+            RecursiveMode::NonRecursive
+        };
+
+        for root in self.context.filter.roots() {
+            println!(
+                "👀 Watching directory: {}{}",
+                root.display(),
+                if self.context.config.recursive {
+                    " (recursive)"
+                } else {
+                    ""
+                }
+            );
+            self._watcher.watch(root, mode)?;
+        }
+
+        // Check for existing CAD files in the watched directories
+        Self::load_directories(self.context.filter.roots().to_vec(), &self.context).await
+    }
+
+    /// Absolute paths of the watched directories
+    pub fn roots(&self) -> &[PathBuf] {
+        self.context.filter.roots()
+    }
+
+    /// Load the files found by [`FileWatcher::scan_files`], reporting failures per file
+    async fn load_directories(directories: Vec<PathBuf>, context: &WatchContext) -> Result<()> {
+        for path in Self::scan_files(directories, context).await? {
+            println!("📄 Found existing CAD file: {}", path.display());
+            if let Err(e) = Self::process_file(&path, context).await {
+                eprintln!(
+                    "❌ Failed to process existing file {}: {}",
+                    path.display(),
+                    e
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// List files in `directories` that pass the filter, descending into subdirectories
+    /// when watching recursively
+    async fn scan_files(
+        mut directories: Vec<PathBuf>,
+        context: &WatchContext,
+    ) -> Result<Vec<PathBuf>> {
+        let mut files = Vec::new();
+
+        while let Some(directory) = directories.pop() {
+            let io_error = |error| CadStreamError::io(&directory, error);
+            let mut entries = tokio::fs::read_dir(&directory).await.map_err(io_error)?;
+
+            while let Some(entry) = entries.next_entry().await.map_err(io_error)? {
+                let path = entry.path();
+                let file_type = entry.file_type().await.map_err(io_error)?;
+
+                if file_type.is_dir() {
+                    if context.config.recursive && context.filter.descends_into(&path) {
+                        directories.push(path);
+                    }
+                } else if context.filter.matches(&path) {
+                    files.push(path);
+                }
+            }
+        }
+
+        files.sort();
+        Ok(files)
+    }
+
+    /// Apply file changes in the order they happened
+    ///
+    /// Loads are debounced per path: while a load is waiting for its file to settle,
+    /// further write events for that path are folded into it. Unloads and renames
+    /// cancel pending loads of the paths they touch.
+    async fn process_changes(mut rx: mpsc::UnboundedReceiver<FileChange>, context: WatchContext) {
+        let mut pending: HashMap<PathBuf, JoinHandle<()>> = HashMap::new();
+
+        while let Some(change) = rx.recv().await {
+            pending.retain(|_, handle| !handle.is_finished());
+
+            let result = match &change {
+                FileChange::Load(path) => {
+                    if !pending.contains_key(path) {
+                        let handle = tokio::spawn({
+                            let path = path.clone();
+                            let context = context.clone();
+                            async move {
+                                if let Err(e) = Self::load_when_stable(&path, &context).await {
+                                    eprintln!(
+                                        "❌ Failed to process file {}: {}",
+                                        path.display(),
+                                        e
+                                    );
+                                }
+                            }
+                        });
+                        pending.insert(path.clone(), handle);
+                    }
+                    Ok(())
+                }
+                FileChange::Unload(path) => {
+                    if let Some(handle) = pending.remove(path) {
+                        handle.abort();
+                    }
+                    Self::unload_file(path, &context).await;
+                    Ok(())
+                }
+                FileChange::Rename { from, to } => {
+                    for path in [from, to] {
+                        if let Some(handle) = pending.remove(path) {
+                            handle.abort();
+                        }
+                    }
+                    Self::rename_file(from, to, &context).await
+                }
+            };
+
+            if let Err(e) = result {
+                eprintln!("❌ Failed to process file change {:?}: {}", change, e);
+            }
+        }
+    }
+
+    /// Wait until size and modification time stop changing for the quiet period
+    async fn wait_until_stable(path: &Path, context: &WatchContext) -> Result<FileStamp> {
+        let debounce = &context.config.debounce;
+        let mut stamp = FileStamp::read(path).await?;
+        let mut last_change = Instant::now();
+
+        while last_change.elapsed() < debounce.quiet_period {
+            tokio::time::sleep(debounce.poll_interval).await;
+
+            let current = FileStamp::read(path).await?;
+            if current != stamp {
+                stamp = current;
+                last_change = Instant::now();
+            }
+        }
+
+        Ok(stamp)
+    }
+
+    /// Debounced load: parse once the file has settled, and parse again if it changed
+    /// while being parsed (retrying failures, which are usually truncation)
+    async fn load_when_stable(path: &Path, context: &WatchContext) -> Result<()> {
+        let max_retries = context.config.debounce.max_retries;
+        let mut attempt = 0;
+
+        loop {
+            let stamp = Self::wait_until_stable(path, context).await?;
+            let result = Self::process_file(path, context).await;
+
+            // Write events that arrived meanwhile were folded into this load
+            let changed = match FileStamp::read(path).await {
+                Ok(current) => current != stamp,
+                Err(_) => false,
+            };
+
+            match result {
+                Ok(()) if !changed => return Ok(()),
+                Ok(()) => {}
+                Err(error) => {
+                    // A file that didn't change since the failed parse is genuinely broken
+                    attempt += 1;
+                    if attempt > max_retries || !changed {
+                        return Err(error);
+                    }
+
+                    println!(
+                        "🔁 {} changed while parsing ({}), retrying ({}/{})",
                         path.display(),
-                        e
+                        error,
+                        attempt,
+                        max_retries
                     );
-                } else {
-                    // Only load the first file found for now
-                    break;
                 }
             }
         }
+    }
+
+    /// Translate a notify event into registry changes for files that pass the filter
+    ///
+    /// Directories don't pass the filter, but removing or renaming one under a root
+    /// affects the models loaded from below it.
+    fn classify_event(event: Event, filter: &WatchFilter) -> Vec<FileChange> {
+        let mut paths = event.paths.into_iter();
+        // What's gone can't be checked on disk, unloading works by prefix so passing
+        // on a file that isn't loaded does nothing
+        let may_be_directory = |path: &Path| {
+            !matches!(event.kind, EventKind::Remove(RemoveKind::File)) && filter.contains(path)
+        };
+
+        match event.kind {
+            EventKind::Create(CreateKind::File)
+            | EventKind::Modify(ModifyKind::Data(_))
+            | EventKind::Modify(ModifyKind::Name(RenameMode::To)) => paths
+                .filter(|path| filter.matches(path))
+                .inspect(|path| println!("📁 Detected CAD file: {}", path.display()))
+                .map(FileChange::Load)
+                .collect(),
+            EventKind::Remove(_) | EventKind::Modify(ModifyKind::Name(RenameMode::From)) => paths
+                .filter(|path| filter.matches(path) || may_be_directory(path))
+                .inspect(|path| {
+                    if filter.matches(path) {
+                        println!("🗑️  CAD file removed: {}", path.display());
+                    }
+                })
+                .map(FileChange::Unload)
+                .collect(),
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => {
+                match (paths.next(), paths.next()) {
+                    // Atomic saves rename a temporary file over the target, so either side
+                    // being a CAD file is relevant
+                    (Some(from), Some(to)) if filter.matches(&from) || filter.matches(&to) => {
+                        println!(
+                            "✏️  CAD file renamed: {} -> {}",
+                            from.display(),
+                            to.display()
+                        );
+                        vec![FileChange::Rename { from, to }]
+                    }
+                    (Some(from), Some(to)) if filter.contains(&from) && to.is_dir() => {
+                        vec![FileChange::Rename { from, to }]
+                    }
+                    _ => Vec::new(),
+                }
+            }
+            // The backend couldn't tell which side of the rename this is, check the disk
+            EventKind::Modify(ModifyKind::Name(_)) => paths
+                .filter(|path| filter.matches(path) || !path.exists() && may_be_directory(path))
+                .map(|path| {
+                    if path.exists() {
+                        FileChange::Load(path)
+                    } else {
+                        FileChange::Unload(path)
+                    }
+                })
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Drop the model loaded from `path`, or the models of its entries if it is an
+    /// archive
+    async fn unload_file(path: &Path, context: &WatchContext) {
+        let mut models = context.models.write().await;
+        let removed: Vec<PathBuf> = models
+            .keys()
+            .filter(|key| key.starts_with(path))
+            .cloned()
+            .collect();
+
+        for key in removed {
+            models.remove(&key);
+            println!("🗑️  Unloaded: {}", key.display());
+            let _ = context.events.send(ModelEvent::Removed { path: key });
+        }
+    }
+
+    /// Move the model loaded from `from` to `to`, or load/unload when only one side
+    /// passes the filter
+    async fn rename_file(from: &Path, to: &Path, context: &WatchContext) -> Result<()> {
+        // A renamed directory has its files loaded under their new paths
+        if to.is_dir() {
+            Self::unload_file(from, context).await;
+            return Self::load_directories(vec![to.to_path_buf()], context).await;
+        }
+
+        if !context.filter.matches(to) {
+            Self::unload_file(from, context).await;
+            return Ok(());
+        }
+
+        let previous = context.models.write().await.remove(from);
+        let Some(previous) = previous else {
+            // Nothing loaded under the old name (e.g. a temporary file, or an archive
+            // whose entries are keyed below it), load the target
+            Self::unload_file(from, context).await;
+            return Self::process_file(to, context).await;
+        };
+
+        let mut model = Arc::unwrap_or_clone(previous);
+        model.name = to
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or("unknown")
+            .to_string();
+        let model = Arc::new(model);
+
+        context
+            .models
+            .write()
+            .await
+            .insert(to.to_path_buf(), model.clone());
+        println!("✏️  Re-keyed: {} -> {}", from.display(), to.display());
+
+        let _ = context.events.send(ModelEvent::Renamed {
+            from: from.to_path_buf(),
+            to: to.to_path_buf(),
+            model,
+        });
 
         Ok(())
     }
 
-    async fn process_file(
-        path: &Path,
-        current_model: &Arc<RwLock<Option<CadModel>>>,
-    ) -> Result<()> {
-        // Add a small delay to ensure file is fully written
-        tokio::time::sleep(Duration::from_millis(100)).await;
+    async fn process_file(path: &Path, context: &WatchContext) -> Result<()> {
+        let metadata = tokio::fs::metadata(path)
+            .await
+            .map_err(|error| CadStreamError::io(path, error))?;
+        // Directories without an extension pass the filter like extension-less files
+        if metadata.is_dir() {
+            return Ok(());
+        }
+
+        if let Some(max_file_size) = context.config.max_file_size {
+            let len = metadata.len();
+            if len > max_file_size {
+                println!(
+                    "⏭️  Skipping {}: {} bytes exceeds the {} byte limit",
+                    path.display(),
+                    len,
+                    max_file_size
+                );
+                return Ok(());
+            }
+        }
+
+        // Compressed files and archives are held to the same limit once unpacked
+        let config = &context.config;
+        let options = ParseOptions {
+            max_decompressed_size: config
+                .parse_options
+                .max_decompressed_size
+                .or(config.max_file_size),
+            ..config.parse_options
+        };
+
+        // Detect the format from the content, the file name is only a hint
+        let (data, file_name) = read_file(path, &options).await?;
+        let loaded = match load_data(&data, file_name, path, &config.parsers, &options) {
+            Ok(loaded) => loaded,
+            Err(error @ CadStreamError::UnsupportedFormat(_))
+                if FileFormat::from_path(path).is_none() =>
+            {
+                println!("⏭️  Skipping {}: {}", path.display(), error);
+                return Ok(());
+            }
+            Err(error) => return Err(error),
+        };
+
+        // Drop entries that were removed from an archive since it was last loaded
+        let stale: Vec<PathBuf> = context
+            .models
+            .read()
+            .await
+            .keys()
+            .filter(|key| key.starts_with(path) && !loaded.iter().any(|l| &l.path == *key))
+            .cloned()
+            .collect();
+        for key in stale {
+            Self::unload_file(&key, context).await;
+        }
 
-        // Detect file format and create appropriate parser
-        let extension = path.extension().and_then(|ext| ext.to_str()).unwrap_or("");
+        for model in loaded {
+            Self::publish_model(model, context).await;
+        }
 
-        let format = FileFormat::from_extension(extension)
-            .ok_or_else(|| anyhow::anyhow!("Unsupported file format: {}", extension))?;
+        Ok(())
+    }
 
-        let parser = ParserFactory::create_parser(format)?;
-        let model = parse_file(parser.as_ref(), path).await?;
+    /// Insert a freshly parsed model into the registry unless its content is unchanged
+    async fn publish_model(loaded: LoadedModel, context: &WatchContext) {
+        let LoadedModel {
+            path,
+            mut model,
+            report,
+            parser_name,
+        } = loaded;
+
+        for warning in &report.warnings {
+            println!("   ⚠️  {}", warning);
+        }
+        model.content_hash = model.compute_content_hash();
+
+        // Saving a file without changes still triggers events, don't reload identical content
+        if let Some(current) = context.models.read().await.get(&path) {
+            if current.content_hash == model.content_hash {
+                println!("⏭️  {} is unchanged, skipping reload", model.name);
+                return;
+            }
+        }
 
         println!(
             "✅ Successfully loaded: {} (using {})",
-            model.name,
-            parser.parser_name()
+            model.name, parser_name
         );
         println!("   📊 {} triangles", model.precision_info.triangle_count);
         println!(
@@ -126,24 +526,20 @@ impl FileWatcher {
             model.precision_info.file_size_bytes
         );
 
-        let mut current = current_model.write().await;
-        *current = Some(model);
-
-        Ok(())
-    }
+        let model = Arc::new(model);
+        context
+            .models
+            .write()
+            .await
+            .insert(path.clone(), model.clone());
 
-    fn is_supported_file(path: &Path) -> bool {
-        if let Some(extension) = path.extension().and_then(|ext| ext.to_str()) {
-            let supported_extensions = ParserFactory::supported_extensions();
-            supported_extensions.contains(&extension.to_lowercase().as_str())
-        } else {
-            false
-        }
+        // Sending only fails when nobody is subscribed, which is fine
+        let _ = context.events.send(ModelEvent::Updated { path, model });
     }
 }
 
-// Helper function to create a simple test STL file
-pub async fn create_test_stl_file() -> Result<()> {
+/// Write a small demo cube to `test_cube.stl` inside `dir`
+pub async fn create_test_stl_file(dir: &Path) -> Result<()> {
     let test_stl = r#"solid test_cube
 facet normal 0.0 0.0 1.0
 outer loop
@@ -175,8 +571,284 @@ endloop
 endfacet
 endsolid test_cube"#;
 
-    tokio::fs::write("test_cube.stl", test_stl).await?;
+    let path = dir.join("test_cube.stl");
+    tokio::fs::write(&path, test_stl)
+        .await
+        .map_err(|error| CadStreamError::io(&path, error))?;
     println!("📝 Created test_cube.stl - a simple cube for demonstration");
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model_events;
+    use crate::watch_config::DebounceConfig;
+    use std::time::Duration;
+
+    fn test_context(config: WatchConfig) -> WatchContext {
+        WatchContext {
+            models: ModelRegistry::default(),
+            events: model_events::channel(),
+            filter: Arc::new(WatchFilter::new(&config).unwrap()),
+            config: Arc::new(config),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_process_file_publishes_only_real_changes() {
+        let dir = std::env::temp_dir().join(format!("cadstream_events_{}", std::process::id()));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let path = dir.join("part.stl");
+
+        let facet = |z: f32| {
+            format!(
+                "solid part\nfacet normal 0 0 1\nouter loop\nvertex 0 0 {z}\nvertex 1 0 {z}\nvertex 0 1 {z}\nendloop\nendfacet\nendsolid part\n"
+            )
+        };
+
+        let context = test_context(WatchConfig::default());
+        let mut rx = context.events.subscribe();
+
+        tokio::fs::write(&path, facet(0.0)).await.unwrap();
+        FileWatcher::process_file(&path, &context).await.unwrap();
+        assert!(matches!(rx.try_recv(), Ok(ModelEvent::Updated { .. })));
+
+        // Same content again: no event
+        FileWatcher::process_file(&path, &context).await.unwrap();
+        assert!(rx.try_recv().is_err());
+
+        // Same triangle count, different geometry: event
+        tokio::fs::write(&path, facet(1.0)).await.unwrap();
+        FileWatcher::process_file(&path, &context).await.unwrap();
+        assert!(matches!(rx.try_recv(), Ok(ModelEvent::Updated { .. })));
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_zip_entries_load_as_separate_models() {
+        use std::io::Write;
+
+        let dir = std::env::temp_dir().join(format!("cadstream_zip_{}", std::process::id()));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let path = dir.join("bundle.zip");
+
+        let write_zip = |names: &[&str]| {
+            let mut writer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+            for name in names {
+                writer
+                    .start_file(*name, zip::write::SimpleFileOptions::default())
+                    .unwrap();
+                writer
+                    .write_all(b"solid a\nfacet normal 0 0 1\nouter loop\nvertex 0 0 0\nvertex 1 0 0\nvertex 0 1 0\nendloop\nendfacet\nendsolid a\n")
+                    .unwrap();
+            }
+            writer.finish().unwrap().into_inner()
+        };
+
+        let context = test_context(WatchConfig::default());
+        assert!(context.filter.matches(&path));
+
+        tokio::fs::write(&path, write_zip(&["a.stl", "sub/b.stl"]))
+            .await
+            .unwrap();
+        FileWatcher::process_file(&path, &context).await.unwrap();
+        let mut keys: Vec<_> = context.models.read().await.keys().cloned().collect();
+        keys.sort();
+        assert_eq!(keys, [path.join("a.stl"), path.join("sub/b.stl")]);
+
+        // An entry removed from the archive is unloaded on the next load
+        tokio::fs::write(&path, write_zip(&["a.stl"]))
+            .await
+            .unwrap();
+        FileWatcher::process_file(&path, &context).await.unwrap();
+        let keys: Vec<_> = context.models.read().await.keys().cloned().collect();
+        assert_eq!(keys, [path.join("a.stl")]);
+
+        FileWatcher::unload_file(&path, &context).await;
+        assert!(context.models.read().await.is_empty());
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_removed_and_renamed_directories_move_their_models() {
+        let dir = std::env::temp_dir().join(format!("cadstream_subdir_{}", std::process::id()));
+        let sub = dir.join("sub");
+        tokio::fs::create_dir_all(&sub).await.unwrap();
+        tokio::fs::write(
+            sub.join("part.stl"),
+            "solid a\nfacet normal 0 0 1\nouter loop\nvertex 0 0 0\nvertex 1 0 0\nvertex 0 1 0\nendloop\nendfacet\nendsolid a\n",
+        )
+        .await
+        .unwrap();
+
+        let context = test_context(WatchConfig {
+            roots: vec![dir.clone()],
+            recursive: true,
+            ..Default::default()
+        });
+        FileWatcher::load_directories(vec![dir.clone()], &context)
+            .await
+            .unwrap();
+        assert!(context
+            .models
+            .read()
+            .await
+            .contains_key(&sub.join("part.stl")));
+
+        // Renaming the directory moves its models
+        let moved = dir.join("moved");
+        tokio::fs::rename(&sub, &moved).await.unwrap();
+        let rename = Event::new(EventKind::Modify(ModifyKind::Name(RenameMode::Both)))
+            .add_path(sub.clone())
+            .add_path(moved.clone());
+        let changes = FileWatcher::classify_event(rename, &context.filter);
+        assert_eq!(
+            changes,
+            vec![FileChange::Rename {
+                from: sub.clone(),
+                to: moved.clone()
+            }]
+        );
+        FileWatcher::rename_file(&sub, &moved, &context)
+            .await
+            .unwrap();
+        let keys: Vec<_> = context.models.read().await.keys().cloned().collect();
+        assert_eq!(keys, [moved.join("part.stl")]);
+
+        // Removing it unloads them
+        tokio::fs::remove_dir_all(&moved).await.unwrap();
+        let remove = Event::new(EventKind::Remove(RemoveKind::Folder)).add_path(moved.clone());
+        let changes = FileWatcher::classify_event(remove, &context.filter);
+        assert_eq!(changes, vec![FileChange::Unload(moved.clone())]);
+        FileWatcher::unload_file(&moved, &context).await;
+        assert!(context.models.read().await.is_empty());
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_load_waits_for_write_to_finish() {
+        let dir = std::env::temp_dir().join(format!("cadstream_debounce_{}", std::process::id()));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let path = dir.join("slow.stl");
+
+        // Binary STL with two facets, written in two chunks
+        let mut data = vec![0u8; 80];
+        data.extend_from_slice(&2u32.to_le_bytes());
+        data.extend_from_slice(&[0u8; 100]);
+        tokio::fs::write(&path, &data[..120]).await.unwrap();
+
+        let writer = tokio::spawn({
+            let path = path.clone();
+            async move {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                tokio::fs::write(&path, &data).await.unwrap();
+            }
+        });
+
+        let context = test_context(WatchConfig {
+            debounce: DebounceConfig {
+                quiet_period: Duration::from_millis(300),
+                poll_interval: Duration::from_millis(20),
+                max_retries: 3,
+            },
+            ..Default::default()
+        });
+
+        FileWatcher::load_when_stable(&path, &context)
+            .await
+            .unwrap();
+        writer.await.unwrap();
+
+        assert_eq!(context.models.read().await[&path].triangles.len(), 2);
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_recursive_scan_applies_filters() {
+        let dir = std::env::temp_dir().join(format!("cadstream_scan_{}", std::process::id()));
+        tokio::fs::create_dir_all(dir.join("sub/tmp"))
+            .await
+            .unwrap();
+        tokio::fs::create_dir_all(dir.join(".git/objects"))
+            .await
+            .unwrap();
+        for file in [
+            "a.stl",
+            "sub/b.stl",
+            "sub/tmp/c.stl",
+            "sub/d_backup.stl",
+            "e.txt",
+            "Makefile",
+            ".git/objects/ab",
+        ] {
+            tokio::fs::write(dir.join(file), b"").await.unwrap();
+        }
+
+        let context = test_context(WatchConfig {
+            roots: vec![dir.clone()],
+            recursive: true,
+            exclude: vec!["**/tmp/**".to_string(), "*_backup.stl".to_string()],
+            ..Default::default()
+        });
+        let files = FileWatcher::scan_files(vec![dir.clone()], &context)
+            .await
+            .unwrap();
+        assert_eq!(files, vec![dir.join("a.stl"), dir.join("sub/b.stl")]);
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[test]
+    fn test_classify_rename_and_remove_events() {
+        let filter = WatchFilter::new(&WatchConfig::default()).unwrap();
+
+        let rename = Event::new(EventKind::Modify(ModifyKind::Name(RenameMode::Both)))
+            .add_path(PathBuf::from("part.stl.tmp"))
+            .add_path(PathBuf::from("part.stl"));
+        assert_eq!(
+            FileWatcher::classify_event(rename, &filter),
+            vec![FileChange::Rename {
+                from: PathBuf::from("part.stl.tmp"),
+                to: PathBuf::from("part.stl"),
+            }]
+        );
+
+        let remove = Event::new(EventKind::Remove(notify::event::RemoveKind::File))
+            .add_path(PathBuf::from("part.stl"))
+            .add_path(PathBuf::from("notes.txt"));
+        assert_eq!(
+            FileWatcher::classify_event(remove, &filter),
+            vec![FileChange::Unload(PathBuf::from("part.stl"))]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rename_re_keys_loaded_model() {
+        let context = test_context(WatchConfig::default());
+        let mut rx = context.events.subscribe();
+
+        let from = PathBuf::from("bracket.stl");
+        let to = PathBuf::from("bracket_v2.stl");
+        let model = crate::cad_data::CadModel::new("bracket.stl".to_string(), Vec::new());
+        context
+            .models
+            .write()
+            .await
+            .insert(from.clone(), Arc::new(model));
+
+        FileWatcher::rename_file(&from, &to, &context)
+            .await
+            .unwrap();
+
+        let registry = context.models.read().await;
+        assert!(!registry.contains_key(&from));
+        assert_eq!(registry[&to].name, "bracket_v2.stl");
+        assert!(matches!(rx.try_recv(), Ok(ModelEvent::Renamed { .. })));
+    }
+}
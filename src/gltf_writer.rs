@@ -0,0 +1,501 @@
+use base64::Engine;
+use glam::Vec3;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::ops::Range;
+use std::path::Path;
+
+use crate::cad_data::{CadModel, Rgba};
+use crate::error::{CadStreamError, Result};
+
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+const FLOAT: u32 = 5126;
+const UNSIGNED_INT: u32 = 5125;
+
+/// Container written by [`GltfWriter`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GltfEncoding {
+    /// `.gltf` JSON with the buffer embedded as a base64 data URI
+    Embedded,
+    /// Binary `.glb`
+    Binary,
+}
+
+/// How model colors are written to the `COLOR_0` attribute
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorMode {
+    /// Every triangle corner keeps its own color, blended across the face
+    #[default]
+    PerVertex,
+    /// Each triangle is flat shaded with the color of its first corner
+    PerTriangle,
+}
+
+/// Serialises models to indexed glTF 2.0 or GLB
+///
+/// Every model becomes a node, and a model with parts gets one child node per part,
+/// plus unnamed ones for triangles outside every part. Empty parts are left out.
+/// Vertices are shared between triangles with the same position, normal and color, so
+/// facet normals stay flat. Colors are converted from sRGB to glTF's linear space.
+#[derive(Debug, Clone, Default)]
+pub struct GltfWriter {
+    pub color_mode: ColorMode,
+}
+
+/// One mesh primitive, before it's written into the shared buffer
+#[derive(Default)]
+struct IndexedPrimitive {
+    positions: Vec<[f32; 3]>,
+    normals: Vec<[f32; 3]>,
+    colors: Vec<Rgba>,
+    indices: Vec<u32>,
+}
+
+/// The JSON document and binary buffer being assembled
+#[derive(Default)]
+struct Document {
+    buffer: Vec<u8>,
+    buffer_views: Vec<Value>,
+    accessors: Vec<Value>,
+    meshes: Vec<Value>,
+    nodes: Vec<Value>,
+}
+
+impl GltfWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub async fn write_file<P: AsRef<Path>>(
+        &self,
+        model: &CadModel,
+        path: P,
+        encoding: GltfEncoding,
+    ) -> Result<()> {
+        let data = self.to_bytes(model, encoding)?;
+
+        tokio::fs::write(&path, data)
+            .await
+            .map_err(|error| CadStreamError::io(path.as_ref(), error))
+    }
+
+    pub fn to_bytes(&self, model: &CadModel, encoding: GltfEncoding) -> Result<Vec<u8>> {
+        self.scene_to_bytes(&[model], encoding)
+    }
+
+    /// Write several models into one scene, one root node each
+    pub fn scene_to_bytes(&self, models: &[&CadModel], encoding: GltfEncoding) -> Result<Vec<u8>> {
+        let mut document = Document::default();
+        let mut roots = Vec::new();
+
+        for model in models {
+            roots.push(document.nodes.len());
+            document.nodes.push(json!({ "name": model.name }));
+            let root = document.nodes.len() - 1;
+
+            // An empty mesh would need accessors with no elements and no bounds
+            let parts = model
+                .covering_parts()
+                .into_iter()
+                .filter(|part| !part.triangles.is_empty());
+
+            if model.parts.is_empty() {
+                for part in parts {
+                    let mesh = self.add_mesh(&mut document, model, part.triangles)?;
+                    document.nodes[root]["mesh"] = json!(mesh);
+                }
+                continue;
+            }
+
+            let mut children = Vec::new();
+            for part in parts {
+                let mesh = self.add_mesh(&mut document, model, part.triangles)?;
+                children.push(document.nodes.len());
+                let mut node = json!({ "mesh": mesh });
+                if !part.name.is_empty() {
+                    node["name"] = json!(part.name);
+                }
+                document.nodes.push(node);
+            }
+            if !children.is_empty() {
+                document.nodes[root]["children"] = json!(children);
+            }
+        }
+
+        // glTF doesn't allow empty mesh, accessor or buffer arrays
+        if document.meshes.is_empty() {
+            return Err(CadStreamError::Export("No triangles to write".to_string()));
+        }
+
+        let mut gltf = json!({
+            "asset": { "version": "2.0", "generator": "cadstream" },
+            "scene": 0,
+            "scenes": [{ "nodes": roots }],
+            "nodes": document.nodes,
+            "meshes": document.meshes,
+            "accessors": document.accessors,
+            "bufferViews": document.buffer_views,
+            "buffers": [{ "byteLength": document.buffer.len() }],
+        });
+
+        match encoding {
+            GltfEncoding::Embedded => {
+                gltf["buffers"][0]["uri"] = json!(format!(
+                    "data:application/octet-stream;base64,{}",
+                    base64::engine::general_purpose::STANDARD.encode(&document.buffer)
+                ));
+                Ok(serde_json::to_vec_pretty(&gltf).map_err(json_error)?)
+            }
+            GltfEncoding::Binary => self.write_glb(
+                &serde_json::to_vec(&gltf).map_err(json_error)?,
+                document.buffer,
+            ),
+        }
+    }
+
+    /// Index the triangles in `range` and append them as a mesh, returning its index
+    fn add_mesh(
+        &self,
+        document: &mut Document,
+        model: &CadModel,
+        range: Range<usize>,
+    ) -> Result<usize> {
+        let primitive = self.index_triangles(model, range)?;
+
+        let mut min = Vec3::splat(f32::INFINITY);
+        let mut max = Vec3::splat(f32::NEG_INFINITY);
+        for position in &primitive.positions {
+            min = min.min(Vec3::from_array(*position));
+            max = max.max(Vec3::from_array(*position));
+        }
+
+        let positions = document.add_accessor(
+            bytemuck::cast_slice(&primitive.positions),
+            ARRAY_BUFFER,
+            json!({ "componentType": FLOAT, "count": primitive.positions.len(), "type": "VEC3",
+                    "min": min.to_array(), "max": max.to_array() }),
+        );
+        let normals = document.add_accessor(
+            bytemuck::cast_slice(&primitive.normals),
+            ARRAY_BUFFER,
+            json!({ "componentType": FLOAT, "count": primitive.normals.len(), "type": "VEC3" }),
+        );
+        let mut attributes = json!({ "POSITION": positions, "NORMAL": normals });
+
+        if model.vertex_colors.is_some() {
+            let linear: Vec<[f32; 4]> = primitive
+                .colors
+                .iter()
+                .map(|c| rgba_to_linear(*c))
+                .collect();
+            attributes["COLOR_0"] = json!(document.add_accessor(
+                bytemuck::cast_slice(&linear),
+                ARRAY_BUFFER,
+                json!({ "componentType": FLOAT, "count": linear.len(), "type": "VEC4" }),
+            ));
+        }
+
+        let indices = document.add_accessor(
+            bytemuck::cast_slice(&primitive.indices),
+            ELEMENT_ARRAY_BUFFER,
+            json!({ "componentType": UNSIGNED_INT, "count": primitive.indices.len(), "type": "SCALAR" }),
+        );
+
+        document.meshes.push(json!({
+            "primitives": [{ "attributes": attributes, "indices": indices }]
+        }));
+        Ok(document.meshes.len() - 1)
+    }
+
+    /// Share vertices between triangles with bit-identical position, normal and color
+    fn index_triangles(&self, model: &CadModel, range: Range<usize>) -> Result<IndexedPrimitive> {
+        let mut primitive = IndexedPrimitive::default();
+        let mut vertex_ids: HashMap<([u32; 3], [u32; 3], Rgba), u32> = HashMap::new();
+
+        for index in range {
+            let triangle = &model.triangles[index];
+            if !triangle.vertices.iter().all(|v| v.is_finite()) {
+                return Err(CadStreamError::Export(format!(
+                    "Triangle {} has a non-finite vertex",
+                    index
+                )));
+            }
+            // NORMAL must be unit length, degenerate triangles get an arbitrary one
+            let normal = triangle
+                .normal
+                .try_normalize()
+                .or_else(|| {
+                    (triangle.vertices[1] - triangle.vertices[0])
+                        .cross(triangle.vertices[2] - triangle.vertices[0])
+                        .try_normalize()
+                })
+                .unwrap_or(Vec3::Z);
+
+            for corner in 0..3 {
+                let color =
+                    model
+                        .vertex_colors
+                        .as_ref()
+                        .map_or([0; 4], |colors| match self.color_mode {
+                            ColorMode::PerVertex => colors[index][corner],
+                            ColorMode::PerTriangle => colors[index][0],
+                        });
+                let position = triangle.vertices[corner];
+                let key = (
+                    position.to_array().map(f32::to_bits),
+                    normal.to_array().map(f32::to_bits),
+                    color,
+                );
+
+                let next_id = u32::try_from(primitive.positions.len()).map_err(|_| {
+                    CadStreamError::Export("Too many vertices for glTF".to_string())
+                })?;
+                let id = *vertex_ids.entry(key).or_insert_with(|| {
+                    primitive.positions.push(position.to_array());
+                    primitive.normals.push(normal.to_array());
+                    primitive.colors.push(color);
+                    next_id
+                });
+                primitive.indices.push(id);
+            }
+        }
+
+        Ok(primitive)
+    }
+
+    /// 12-byte header, then the JSON and BIN chunks padded to 4 bytes
+    fn write_glb(&self, json: &[u8], mut buffer: Vec<u8>) -> Result<Vec<u8>> {
+        let mut json = json.to_vec();
+        json.resize(json.len().next_multiple_of(4), b' ');
+        buffer.resize(buffer.len().next_multiple_of(4), 0);
+
+        // Every length is a u32, the chunk lengths fit if the total does
+        let total_len = 12 + 8 + json.len() + 8 + buffer.len();
+        let total_len_u32 = u32::try_from(total_len).map_err(|_| {
+            CadStreamError::Export(format!(
+                "GLB would be {} bytes, at most 4 GiB are allowed",
+                total_len
+            ))
+        })?;
+
+        let mut glb = Vec::with_capacity(total_len);
+        glb.extend_from_slice(b"glTF");
+        glb.extend_from_slice(&2u32.to_le_bytes());
+        glb.extend_from_slice(&total_len_u32.to_le_bytes());
+        glb.extend_from_slice(&(json.len() as u32).to_le_bytes());
+        glb.extend_from_slice(b"JSON");
+        glb.extend_from_slice(&json);
+        glb.extend_from_slice(&(buffer.len() as u32).to_le_bytes());
+        glb.extend_from_slice(b"BIN\0");
+        glb.extend_from_slice(&buffer);
+        Ok(glb)
+    }
+}
+
+impl Document {
+    /// Append `data` as a buffer view with an accessor described by `accessor`,
+    /// returning the accessor index
+    fn add_accessor(&mut self, data: &[u8], target: u32, mut accessor: Value) -> usize {
+        // Every element type written is 4-byte aligned, so views stay aligned
+        self.buffer_views.push(json!({
+            "buffer": 0,
+            "byteOffset": self.buffer.len(),
+            "byteLength": data.len(),
+            "target": target,
+        }));
+        self.buffer.extend_from_slice(data);
+
+        accessor["bufferView"] = json!(self.buffer_views.len() - 1);
+        self.accessors.push(accessor);
+        self.accessors.len() - 1
+    }
+}
+
+fn json_error(error: serde_json::Error) -> CadStreamError {
+    CadStreamError::Export(format!("Could not serialise glTF JSON: {}", error))
+}
+
+/// Model colors are 8-bit sRGB, glTF colors are linear
+fn rgba_to_linear([r, g, b, a]: Rgba) -> [f32; 4] {
+    let decode = |value: u8| {
+        let srgb = value as f32 / 255.0;
+        if srgb <= 0.04045 {
+            srgb / 12.92
+        } else {
+            ((srgb + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    [decode(r), decode(g), decode(b), a as f32 / 255.0]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cad_data::{MeshPart, Triangle};
+
+    /// A unit square in two parts, each made of two triangles sharing an edge
+    fn sample_model() -> CadModel {
+        let square = |z: f32| {
+            [
+                [
+                    Vec3::new(0.0, 0.0, z),
+                    Vec3::new(1.0, 0.0, z),
+                    Vec3::new(1.0, 1.0, z),
+                ],
+                [
+                    Vec3::new(0.0, 0.0, z),
+                    Vec3::new(1.0, 1.0, z),
+                    Vec3::new(0.0, 1.0, z),
+                ],
+            ]
+        };
+        let triangles = square(0.0)
+            .into_iter()
+            .chain(square(2.0))
+            .map(|vertices| Triangle {
+                vertices,
+                normal: Vec3::Z,
+            })
+            .collect();
+
+        let mut model = CadModel::new("squares.stl".to_string(), triangles);
+        model.parts = vec![
+            MeshPart {
+                name: "bottom".to_string(),
+                triangles: 0..2,
+            },
+            MeshPart {
+                name: "top".to_string(),
+                triangles: 2..4,
+            },
+        ];
+        model.vertex_colors = Some(vec![
+            [[255, 0, 0, 255]; 3],
+            [[255, 0, 0, 255]; 3],
+            [[10, 128, 250, 255], [0, 255, 0, 128], [0, 255, 0, 128]],
+            [[0, 255, 0, 128]; 3],
+        ]);
+        model
+    }
+
+    #[test]
+    fn test_vertices_are_shared_per_part() {
+        let writer = GltfWriter::new();
+        let bytes = writer
+            .to_bytes(&sample_model(), GltfEncoding::Embedded)
+            .unwrap();
+        let gltf: Value = serde_json::from_slice(&bytes).unwrap();
+
+        assert_eq!(gltf["nodes"][0]["name"], "squares.stl");
+        assert_eq!(gltf["nodes"][0]["children"], json!([1, 2]));
+        assert_eq!(gltf["nodes"][2]["name"], "top");
+
+        // Both squares have 4 distinct corners, the top one splits a corner by color
+        let primitive = |mesh: usize| &gltf["meshes"][mesh]["primitives"][0];
+        let count = |accessor: &Value| {
+            gltf["accessors"][accessor.as_u64().unwrap() as usize]["count"].clone()
+        };
+        assert_eq!(count(&primitive(0)["attributes"]["POSITION"]), 4);
+        assert_eq!(count(&primitive(1)["attributes"]["POSITION"]), 5);
+        assert_eq!(count(&primitive(1)["indices"]), 6);
+
+        let writer = GltfWriter {
+            color_mode: ColorMode::PerTriangle,
+        };
+        let bytes = writer
+            .to_bytes(&sample_model(), GltfEncoding::Embedded)
+            .unwrap();
+        let gltf: Value = serde_json::from_slice(&bytes).unwrap();
+        let positions = &gltf["meshes"][1]["primitives"][0]["attributes"]["POSITION"];
+        assert_eq!(
+            gltf["accessors"][positions.as_u64().unwrap() as usize]["count"],
+            6
+        );
+    }
+
+    #[test]
+    fn test_every_triangle_is_written_and_empty_meshes_skipped() {
+        let mut model = sample_model();
+        model.parts = vec![
+            MeshPart {
+                name: "bottom".to_string(),
+                triangles: 1..2,
+            },
+            MeshPart {
+                name: "empty".to_string(),
+                triangles: 2..2,
+            },
+        ];
+        let bytes = GltfWriter::new()
+            .to_bytes(&model, GltfEncoding::Embedded)
+            .unwrap();
+        let gltf: Value = serde_json::from_slice(&bytes).unwrap();
+
+        let nodes: Vec<_> = gltf["nodes"].as_array().unwrap().iter().collect();
+        assert_eq!(nodes.len(), 4);
+        assert_eq!(nodes[2]["name"], "bottom");
+        let indices: u64 = gltf["meshes"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|mesh| {
+                let accessor = mesh["primitives"][0]["indices"].as_u64().unwrap();
+                gltf["accessors"][accessor as usize]["count"]
+                    .as_u64()
+                    .unwrap()
+            })
+            .sum();
+        assert_eq!(indices, 3 * 4);
+        for accessor in gltf["accessors"].as_array().unwrap() {
+            assert!(accessor["count"].as_u64().unwrap() > 0);
+        }
+
+        let empty = CadModel::new("empty.stl".to_string(), Vec::new());
+        for encoding in [GltfEncoding::Embedded, GltfEncoding::Binary] {
+            assert!(GltfWriter::new().to_bytes(&empty, encoding).is_err());
+        }
+    }
+
+    #[test]
+    fn test_degenerate_and_non_finite_triangles() {
+        let degenerate = Triangle {
+            vertices: [Vec3::ZERO, Vec3::X, Vec3::X * 2.0],
+            normal: Vec3::ZERO,
+        };
+        let model = CadModel::new("sliver.stl".to_string(), vec![degenerate.clone()]);
+        let primitive = GltfWriter::new().index_triangles(&model, 0..1).unwrap();
+        assert!(primitive.normals.iter().all(|&n| n == [0.0, 0.0, 1.0]));
+
+        let mut broken = degenerate;
+        broken.vertices[2].y = f32::NAN;
+        let model = CadModel::new("broken.stl".to_string(), vec![broken]);
+        assert!(matches!(
+            GltfWriter::new().to_bytes(&model, GltfEncoding::Binary),
+            Err(CadStreamError::Export(_))
+        ));
+    }
+
+    #[cfg(feature = "gltf-parser")]
+    #[test]
+    fn test_round_trip_through_gltf_parser() {
+        use crate::parsers::{gltf_parser::GltfParser, FileParser};
+
+        let model = sample_model();
+        for encoding in [GltfEncoding::Embedded, GltfEncoding::Binary] {
+            let bytes = GltfWriter::new().to_bytes(&model, encoding).unwrap();
+            let parsed = GltfParser::new()
+                .parse_data(&bytes, "squares.gltf".to_string())
+                .unwrap();
+
+            assert_eq!(parsed.triangles.len(), model.triangles.len());
+            for (a, b) in parsed.triangles.iter().zip(&model.triangles) {
+                assert_eq!(a.vertices, b.vertices);
+                assert!(a.normal.abs_diff_eq(b.normal, 1e-6));
+            }
+            let names: Vec<_> = parsed.parts.iter().map(|p| p.name.as_str()).collect();
+            assert_eq!(names, ["bottom", "top"]);
+            assert_eq!(parsed.vertex_colors, model.vertex_colors);
+        }
+    }
+}
@@ -1,5 +1,13 @@
+pub mod archive;
 pub mod cad_data;
+pub mod error;
 pub mod file_watcher;
+pub mod gltf_writer;
+pub mod model_events;
 pub mod parsers;
 pub mod rerun_renderer;
 pub mod stl_parser;
+pub mod stl_stream;
+pub mod stl_writer;
+pub mod validation;
+pub mod watch_config;
@@ -1,56 +1,342 @@
-mod cad_data;
-mod file_watcher;
-mod parsers;
-mod rerun_renderer;
-mod stl_parser;
+use anyhow::{Context, Result};
+use clap::{Args, Parser, Subcommand};
+use std::path::{Path, PathBuf};
+use tokio::sync::broadcast;
 
-use anyhow::Result;
-use std::sync::Arc;
-use tokio::sync::RwLock;
+use cadstream::{
+    cad_data::{CadModel, DEFAULT_WELD_EPSILON},
+    file_watcher::{self, FileWatcher},
+    gltf_writer::{ColorMode, GltfEncoding, GltfWriter},
+    model_events::{self, ModelEvent, ModelRegistry},
+    parsers::{load_file, FileFormat, ParseMode, ParseOptions, ParserRegistry, StlBackend},
+    rerun_renderer::RerunRenderer,
+    stl_parser::StlEncoding,
+    stl_writer::StlWriter,
+    validation,
+    watch_config::WatchConfig,
+};
 
-use crate::{cad_data::CadModel, file_watcher::FileWatcher, rerun_renderer::RerunRenderer};
+/// Stream CAD files into a Rerun viewer and inspect them from the command line
+#[derive(Debug, Parser)]
+#[command(name = "cadstream", version, about)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Port of the Rerun gRPC server
+    #[arg(long, global = true, default_value_t = rerun::DEFAULT_SERVER_PORT)]
+    port: u16,
+
+    /// STL parser to use: auto, custom or stl-io
+    #[arg(long, global = true, default_value = "auto")]
+    parser: StlBackend,
+
+    /// Skip malformed facets and solids instead of failing, printing what was skipped
+    #[arg(long, global = true)]
+    lenient: bool,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Watch directories and stream every CAD file in them (the default)
+    Watch(WatchArgs),
+    /// Stream a single file to the viewer
+    View { file: PathBuf },
+    /// Print statistics about a file
+    Info { file: PathBuf },
+    /// Convert a file to another format
+    Convert(ConvertArgs),
+    /// Check a file for mesh defects, exits with an error if any are found
+    Validate { file: PathBuf },
+}
+
+#[derive(Debug, Args, Default)]
+struct WatchArgs {
+    /// Directories to watch
+    #[arg(default_value = ".")]
+    dirs: Vec<PathBuf>,
+
+    /// Also watch subdirectories
+    #[arg(short, long)]
+    recursive: bool,
+
+    /// Only load files matching this glob (repeatable)
+    #[arg(long)]
+    include: Vec<String>,
+
+    /// Skip files matching this glob (repeatable)
+    #[arg(long)]
+    exclude: Vec<String>,
+
+    /// Skip files larger than this many bytes
+    #[arg(long)]
+    max_file_size: Option<u64>,
+
+    /// Write a demo test_cube.stl into the first directory before watching
+    #[arg(long)]
+    demo_cube: bool,
+}
+
+#[derive(Debug, Args)]
+struct ConvertArgs {
+    input: PathBuf,
+    output: PathBuf,
+
+    /// Write ASCII instead of binary STL
+    #[arg(long)]
+    ascii: bool,
+
+    /// Digits after the decimal point in ASCII output, exact by default
+    #[arg(long)]
+    precision: Option<usize>,
+
+    /// Solid name in ASCII output, defaults to the input file name
+    #[arg(long)]
+    solid_name: Option<String>,
+
+    /// Flat shade each triangle with its first corner color in glTF output
+    #[arg(long)]
+    triangle_colors: bool,
+}
+
+/// How files named on the command line are parsed
+#[derive(Debug, Clone)]
+struct LoadOptions {
+    parsers: ParserRegistry,
+    parse: ParseOptions,
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
     env_logger::init();
 
-    // Create a test STL file if no STL files exist
-    let current_dir = std::env::current_dir()?;
-    let mut has_stl_files = false;
-    let mut entries = tokio::fs::read_dir(&current_dir).await?;
-    while let Some(entry) = entries.next_entry().await? {
-        if entry.path().extension().and_then(|ext| ext.to_str()) == Some("stl") {
-            has_stl_files = true;
-            break;
+    let cli = Cli::parse();
+    let command = cli.command.unwrap_or_else(|| {
+        Command::Watch(WatchArgs {
+            dirs: vec![PathBuf::from(".")],
+            ..Default::default()
+        })
+    });
+
+    let mut parsers = ParserRegistry::new();
+    if let Some(name) = cli.parser.parser_name() {
+        parsers.prefer(FileFormat::Stl, name)?;
+    }
+
+    let load = LoadOptions {
+        parsers,
+        parse: ParseOptions {
+            mode: if cli.lenient {
+                ParseMode::Lenient
+            } else {
+                ParseMode::Strict
+            },
+            ..Default::default()
+        },
+    };
+
+    match command {
+        Command::Watch(args) => run_watch(args, cli.port, load).await,
+        Command::View { file } => run_view(&file, cli.port, &load).await,
+        Command::Info { file } => run_info(&file, &load).await,
+        Command::Convert(args) => run_convert(args, &load).await,
+        Command::Validate { file } => run_validate(&file, &load).await,
+    }
+}
+
+async fn run_watch(args: WatchArgs, port: u16, load: LoadOptions) -> Result<()> {
+    if args.demo_cube {
+        let dir = args.dirs.first().map_or(Path::new("."), PathBuf::as_path);
+        file_watcher::create_test_stl_file(dir).await?;
+    }
+
+    let config = WatchConfig {
+        roots: args.dirs,
+        recursive: args.recursive,
+        include: args.include,
+        exclude: args.exclude,
+        max_file_size: args.max_file_size,
+        parsers: load.parsers,
+        parse_options: load.parse,
+        ..Default::default()
+    };
+
+    // Setup the model registry and the update channel between watcher and renderer
+    let models = ModelRegistry::default();
+    let events = model_events::channel();
+    let renderer_events = events.subscribe();
+    let file_watcher = FileWatcher::with_config(models.clone(), events, config).await?;
+    let roots = file_watcher.roots().to_vec();
+
+    // Start Rerun visualization
+    run_rerun_mode(models, renderer_events, port, &roots).await
+}
+
+async fn run_view(file: &Path, port: u16, load: &LoadOptions) -> Result<()> {
+    let model = load_model(file, load).await?;
+
+    let models = ModelRegistry::default();
+    models
+        .write()
+        .await
+        .insert(file.to_path_buf(), model.into());
+
+    // Nothing publishes updates, but the sender must outlive the renderer
+    let events = model_events::channel();
+    let renderer_events = events.subscribe();
+    let roots = file
+        .parent()
+        .map(Path::to_path_buf)
+        .into_iter()
+        .collect::<Vec<_>>();
+    let result = run_rerun_mode(models, renderer_events, port, &roots).await;
+    drop(events);
+    result
+}
+
+async fn run_info(file: &Path, load: &LoadOptions) -> Result<()> {
+    let model = load_model(file, load).await?;
+    let size = model.size();
+    let bounds = &model.bounds;
+
+    println!("📄 {}", file.display());
+    println!("   📊 {} triangles", model.precision_info.triangle_count);
+    println!(
+        "   🔗 {} unique vertices",
+        model.to_indexed(DEFAULT_WELD_EPSILON).positions.len()
+    );
+    println!("   📏 Size: {:.3} x {:.3} x {:.3}", size.x, size.y, size.z);
+    println!(
+        "   📦 Bounds: ({:.3}, {:.3}, {:.3}) - ({:.3}, {:.3}, {:.3})",
+        bounds.min.x, bounds.min.y, bounds.min.z, bounds.max.x, bounds.max.y, bounds.max.z
+    );
+    println!("   💾 {} bytes", model.precision_info.file_size_bytes);
+    println!(
+        "   🎨 Vertex colors: {}",
+        if model.vertex_colors.is_some() {
+            "yes"
+        } else {
+            "no"
         }
+    );
+    for part in &model.parts {
+        println!("   🧩 {}: {} triangles", part.name, part.triangles.len());
     }
 
-    if !has_stl_files {
-        if let Err(e) = file_watcher::create_test_stl_file().await {
-            eprintln!("Failed to create test STL file: {}", e);
+    Ok(())
+}
+
+async fn run_convert(args: ConvertArgs, load: &LoadOptions) -> Result<()> {
+    let model = load_model(&args.input, load).await?;
+
+    let extension = args
+        .output
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or("");
+    match FileFormat::from_extension(extension) {
+        Some(FileFormat::Stl) => {
+            let writer = StlWriter {
+                precision: args.precision,
+                solid_name: args.solid_name,
+                ..Default::default()
+            };
+            let encoding = if args.ascii {
+                StlEncoding::Ascii
+            } else {
+                StlEncoding::Binary
+            };
+            writer.write_file(&model, &args.output, encoding).await?;
+        }
+        Some(FileFormat::Gltf) => {
+            let writer = GltfWriter {
+                color_mode: if args.triangle_colors {
+                    ColorMode::PerTriangle
+                } else {
+                    ColorMode::PerVertex
+                },
+            };
+            let encoding = if extension.eq_ignore_ascii_case("glb") {
+                GltfEncoding::Binary
+            } else {
+                GltfEncoding::Embedded
+            };
+            writer.write_file(&model, &args.output, encoding).await?;
         }
+        _ => anyhow::bail!(
+            "Cannot write {}: no writer is available for this format yet",
+            args.output.display()
+        ),
     }
 
-    // Setup shared model state
-    let current_model = Arc::new(RwLock::new(None));
-    let _file_watcher = FileWatcher::new(current_model.clone()).await?;
+    println!(
+        "💾 Wrote {} triangles to {}",
+        model.triangles.len(),
+        args.output.display()
+    );
+    Ok(())
+}
 
-    // Start Rerun visualization
-    run_rerun_mode(current_model).await
+async fn run_validate(file: &Path, load: &LoadOptions) -> Result<()> {
+    let model = load_model(file, load).await?;
+    let report = validation::validate(&model);
+
+    println!("🔍 {}", file.display());
+    println!("{}", report);
+
+    if !report.is_watertight() {
+        println!("⚠️  Mesh is not watertight");
+    }
+
+    if report.has_errors() {
+        anyhow::bail!("{} has invalid geometry", file.display());
+    }
+
+    println!("✅ No errors found");
+    Ok(())
+}
+
+async fn load_model(path: &Path, load: &LoadOptions) -> Result<CadModel> {
+    let mut loaded = load_file(path, &load.parsers, &load.parse)
+        .await
+        .with_context(|| format!("Failed to load {}", path.display()))?;
+
+    for warning in loaded.iter().flat_map(|loaded| &loaded.report.warnings) {
+        println!("⚠️  {}", warning);
+    }
+
+    // The entries of an archive become parts of one model
+    if loaded.len() == 1 {
+        return Ok(loaded.remove(0).model);
+    }
+    let name = path.file_name().map_or_else(
+        || path.display().to_string(),
+        |n| n.to_string_lossy().into_owned(),
+    );
+    Ok(CadModel::merge(
+        name,
+        loaded.into_iter().map(|loaded| loaded.model).collect(),
+    ))
 }
 
-async fn run_rerun_mode(current_model: Arc<RwLock<Option<CadModel>>>) -> Result<()> {
+async fn run_rerun_mode(
+    models: ModelRegistry,
+    events: broadcast::Receiver<ModelEvent>,
+    port: u16,
+    roots: &[PathBuf],
+) -> Result<()> {
     println!("🌐 Starting CAD Stream Processor with Rerun visualization");
     println!("----------------------------------------------------------");
     println!("🚀 Rerun gRPC server is starting...");
 
-    let rerun_renderer = RerunRenderer::new(current_model)?;
+    let mut rerun_renderer = RerunRenderer::with_port(models, events, port)?;
+    rerun_renderer.set_roots(roots);
 
-    println!("✅ Rerun server is running on port 9876");
+    println!("✅ Rerun server is running on port {}", port);
     println!("📱 To view the data:");
     println!("   1. Install Rerun viewer: pip install rerun-sdk");
-    println!("   2. Connect with: rerun --connect 127.0.0.1:9876");
-    println!("   3. Or open Rerun app and connect to: 127.0.0.1:9876");
+    println!("   2. Connect with: rerun --connect 127.0.0.1:{}", port);
+    println!("   3. Or open Rerun app and connect to: 127.0.0.1:{}", port);
     println!("----------------------------------------------------------");
 
     rerun_renderer.run().await
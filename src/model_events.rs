@@ -0,0 +1,37 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::{broadcast, RwLock};
+
+use crate::cad_data::CadModel;
+
+/// Every loaded model keyed by its source path, shared between the file watcher and
+/// its consumers
+pub type ModelRegistry = Arc<RwLock<HashMap<PathBuf, Arc<CadModel>>>>;
+
+/// Number of events buffered per subscriber before it starts lagging
+pub const MODEL_EVENT_CAPACITY: usize = 16;
+
+/// Notification published by the `FileWatcher` whenever the set of loaded models changes
+#[derive(Debug, Clone)]
+pub enum ModelEvent {
+    /// A model was loaded, or reloaded with different content
+    Updated { path: PathBuf, model: Arc<CadModel> },
+    /// The source file was deleted or moved out of the watched set
+    Removed { path: PathBuf },
+    /// The source file was renamed, the model itself is unchanged
+    Renamed {
+        from: PathBuf,
+        to: PathBuf,
+        model: Arc<CadModel>,
+    },
+}
+
+/// Create the broadcast channel used to publish model events
+///
+/// Consumers call `subscribe()` on the sender. A subscriber that falls more than
+/// [`MODEL_EVENT_CAPACITY`] events behind receives `RecvError::Lagged` and should
+/// resync from the [`ModelRegistry`].
+pub fn channel() -> broadcast::Sender<ModelEvent> {
+    broadcast::channel(MODEL_EVENT_CAPACITY).0
+}
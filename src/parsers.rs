@@ -1,15 +1,24 @@
+use crate::archive::{self, ArchiveEntry};
 use crate::cad_data::CadModel;
-use anyhow::{Context, Result};
-use std::path::Path;
+use crate::error::{CadStreamError, Result};
+use crate::stl_parser::ENCODING_PROBE_LEN;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
 /// Supported CAD file formats
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum FileFormat {
     Stl,
+    Obj,
+    Ply,
+    Gltf,
+    ThreeMf,
     // Future formats can be added here
-    // Obj,
-    // Ply,
     // Step,
+    /// A format read by a parser registered at runtime, identified by its lowercase
+    /// file extension, see [`ParserRegistry`]
+    Custom(&'static str),
 }
 
 impl FileFormat {
@@ -17,20 +26,225 @@ impl FileFormat {
     pub fn from_extension(extension: &str) -> Option<Self> {
         match extension.to_lowercase().as_str() {
             "stl" => Some(FileFormat::Stl),
-            // "obj" => Some(FileFormat::Obj),
-            // "ply" => Some(FileFormat::Ply),
+            "obj" => Some(FileFormat::Obj),
+            "ply" => Some(FileFormat::Ply),
+            "gltf" | "glb" => Some(FileFormat::Gltf),
+            "3mf" => Some(FileFormat::ThreeMf),
             // "step" | "stp" => Some(FileFormat::Step),
             _ => None,
         }
     }
 
     /// Get file extensions for this format
-    pub fn extensions(&self) -> &'static [&'static str] {
+    pub fn extensions(&self) -> &[&'static str] {
         match self {
             FileFormat::Stl => &["stl"],
-            // FileFormat::Obj => &["obj"],
-            // FileFormat::Ply => &["ply"],
+            FileFormat::Obj => &["obj"],
+            FileFormat::Ply => &["ply"],
+            FileFormat::Gltf => &["gltf", "glb"],
+            FileFormat::ThreeMf => &["3mf"],
             // FileFormat::Step => &["step", "stp"],
+            FileFormat::Custom(extension) => std::slice::from_ref(extension),
+        }
+    }
+
+    /// Detect the format from any extension in the file name, so `part.STL.tmp` is STL
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let (_, extensions) = path.file_name()?.to_str()?.split_once('.')?;
+        extensions.rsplit('.').find_map(Self::from_extension)
+    }
+
+    /// The first of `formats` named by any extension of `path`, like
+    /// [`FileFormat::from_path`] but also for custom formats
+    pub fn find_in_path(path: &Path, formats: &[FileFormat]) -> Option<Self> {
+        let (_, extensions) = path.file_name()?.to_str()?.split_once('.')?;
+        extensions.rsplit('.').find_map(|extension| {
+            let extension = extension.to_lowercase();
+            formats
+                .iter()
+                .find(|format| format.extensions().contains(&extension.as_str()))
+                .copied()
+        })
+    }
+
+    /// Identify the format of `bytes` from magic numbers and header structure, falling
+    /// back to the file name of `path` when the content is ambiguous
+    ///
+    /// Recognised formats without a parser (STEP, zip archives other than 3MF) are
+    /// reported as unsupported rather than handed to a parser that would misread them.
+    pub fn detect(bytes: &[u8], path: &Path) -> Result<Self> {
+        let hint = Self::from_path(path);
+        let text = bytes.trim_ascii_start();
+
+        if bytes.starts_with(b"ply\n") || bytes.starts_with(b"ply\r\n") {
+            return Ok(FileFormat::Ply);
+        }
+        if text.starts_with(b"ISO-10303-21;") {
+            return Err(CadStreamError::UnsupportedFormat("STEP".to_string()));
+        }
+        if bytes.starts_with(b"glTF") || (text.starts_with(b"{") && contains(bytes, b"\"asset\"")) {
+            return Ok(FileFormat::Gltf);
+        }
+        if bytes.starts_with(b"PK\x03\x04") {
+            if contains(bytes, b"3D/3dmodel.model") || hint == Some(FileFormat::ThreeMf) {
+                return Ok(FileFormat::ThreeMf);
+            }
+            return Err(CadStreamError::UnsupportedFormat("zip archive".to_string()));
+        }
+
+        // A binary STL header may start with "solid", so the size invariant goes first
+        if bytes.len() >= 84 {
+            let facet_count = u32::from_le_bytes([bytes[80], bytes[81], bytes[82], bytes[83]]);
+            if 84 + 50 * facet_count as u64 == bytes.len() as u64 {
+                return Ok(FileFormat::Stl);
+            }
+        }
+        if text.starts_with(b"solid") {
+            return Ok(FileFormat::Stl);
+        }
+        if hint.is_none() && Self::looks_like_obj(text) {
+            return Ok(FileFormat::Obj);
+        }
+
+        hint.ok_or_else(|| {
+            CadStreamError::UnsupportedFormat(format!("unrecognised content in {}", path.display()))
+        })
+    }
+
+    /// OBJ has no magic number, accept text whose first statement is an OBJ keyword
+    fn looks_like_obj(text: &[u8]) -> bool {
+        const KEYWORDS: &[&str] = &["v", "vn", "vt", "f", "o", "g", "mtllib", "usemtl", "s"];
+
+        String::from_utf8_lossy(&text[..text.len().min(ENCODING_PROBE_LEN)])
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty() && !line.starts_with('#'))
+            .and_then(|line| line.split_whitespace().next())
+            .is_some_and(|keyword| KEYWORDS.contains(&keyword))
+    }
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack
+        .windows(needle.len())
+        .any(|window| window == needle)
+}
+
+/// STL parser implementation to use when more than one is compiled in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StlBackend {
+    /// Prefer `stl_io` when enabled, otherwise the custom parser
+    #[default]
+    Auto,
+    Custom,
+    StlIo,
+}
+
+impl StlBackend {
+    /// Name of the registered parser this backend selects, `None` leaves the choice to
+    /// the [`ParserRegistry`] priorities
+    pub fn parser_name(self) -> Option<&'static str> {
+        match self {
+            StlBackend::Auto => None,
+            StlBackend::Custom => Some("Custom STL Parser"),
+            StlBackend::StlIo => Some("stl_io Parser"),
+        }
+    }
+}
+
+impl FromStr for StlBackend {
+    type Err = CadStreamError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "auto" => Ok(StlBackend::Auto),
+            "custom" => Ok(StlBackend::Custom),
+            "stl-io" | "stl_io" => Ok(StlBackend::StlIo),
+            _ => Err(CadStreamError::ParserUnavailable(format!(
+                "Unknown STL parser '{}', expected auto, custom or stl-io",
+                s
+            ))),
+        }
+    }
+}
+
+/// How parsers treat malformed input
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ParseMode {
+    /// Fail on the first structural error
+    #[default]
+    Strict,
+    /// Skip malformed elements and report them as warnings
+    Lenient,
+}
+
+/// Options passed to [`FileParser::parse_data_with_options`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ParseOptions {
+    pub mode: ParseMode,
+    /// Bytes a compressed file, or all entries of a zip archive together, may unpack
+    /// to, [`archive::DEFAULT_MAX_DECOMPRESSED_SIZE`] when `None`
+    pub max_decompressed_size: Option<u64>,
+    /// Bytes a line may have when reading text from a stream,
+    /// [`stl_stream::DEFAULT_MAX_LINE_LENGTH`] when `None`
+    ///
+    /// [`stl_stream::DEFAULT_MAX_LINE_LENGTH`]: crate::stl_stream::DEFAULT_MAX_LINE_LENGTH
+    pub max_line_length: Option<usize>,
+}
+
+impl ParseOptions {
+    pub fn decompressed_size_limit(&self) -> u64 {
+        self.max_decompressed_size
+            .unwrap_or(archive::DEFAULT_MAX_DECOMPRESSED_SIZE)
+    }
+
+    pub fn line_length_limit(&self) -> usize {
+        self.max_line_length
+            .unwrap_or(crate::stl_stream::DEFAULT_MAX_LINE_LENGTH)
+    }
+
+    /// Most triangles, and placed objects, a scene may expand to by instancing
+    ///
+    /// As many binary STL facets as fit in [`ParseOptions::decompressed_size_limit`].
+    pub(crate) fn triangle_limit(&self) -> usize {
+        const BINARY_FACET_BYTES: u64 = 50;
+        usize::try_from(self.decompressed_size_limit() / BINARY_FACET_BYTES).unwrap_or(usize::MAX)
+    }
+}
+
+/// Where in the input a problem was found
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SourceLocation {
+    /// 1-based line and column in a text format
+    Line { line: usize, column: usize },
+    /// Offset from the start of a binary file
+    ByteOffset(usize),
+}
+
+impl fmt::Display for SourceLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SourceLocation::Line { line, column } => write!(f, "line {}, column {}", line, column),
+            SourceLocation::ByteOffset(offset) => write!(f, "byte {}", offset),
+        }
+    }
+}
+
+/// Problems that were skipped over while parsing in lenient mode
+#[derive(Debug, Default)]
+pub struct ParseReport {
+    pub warnings: Vec<CadStreamError>,
+}
+
+impl ParseReport {
+    /// Fail with `error` in strict mode, otherwise keep it as a warning
+    pub fn record(&mut self, mode: ParseMode, error: CadStreamError) -> Result<()> {
+        match mode {
+            ParseMode::Strict => Err(error),
+            ParseMode::Lenient => {
+                self.warnings.push(error);
+                Ok(())
+            }
         }
     }
 }
@@ -40,6 +254,32 @@ pub trait FileParser: Send + Sync {
     /// Parse raw file data
     fn parse_data(&self, data: &[u8], name: String) -> Result<CadModel>;
 
+    /// Parse raw file data, returning the problems skipped in lenient mode
+    ///
+    /// Parsers without lenient support always parse strictly and report nothing.
+    fn parse_data_with_options(
+        &self,
+        data: &[u8],
+        name: String,
+        _options: &ParseOptions,
+    ) -> Result<(CadModel, ParseReport)> {
+        Ok((self.parse_data(data, name)?, ParseReport::default()))
+    }
+
+    /// Parse data read from `path`, for formats whose files reference other files
+    /// next to them
+    ///
+    /// The default ignores the path and calls [`FileParser::parse_data_with_options`].
+    fn parse_data_at(
+        &self,
+        data: &[u8],
+        name: String,
+        _path: &Path,
+        options: &ParseOptions,
+    ) -> Result<(CadModel, ParseReport)> {
+        self.parse_data_with_options(data, name, options)
+    }
+
     /// Get supported file formats
     fn supported_formats(&self) -> &[FileFormat];
 
@@ -47,53 +287,341 @@ pub trait FileParser: Send + Sync {
     fn parser_name(&self) -> &'static str;
 }
 
+/// A model read from a file, or from one entry of a zip archive
+#[derive(Debug)]
+pub struct LoadedModel {
+    /// The file, or for archive entries the entry below the archive, e.g. `parts.zip/bracket.stl`
+    pub path: PathBuf,
+    pub model: CadModel,
+    pub report: ParseReport,
+    pub parser_name: &'static str,
+}
+
 /// Async wrapper for file parsing
-pub async fn parse_file<P: AsRef<Path>>(parser: &dyn FileParser, path: P) -> Result<CadModel> {
-    let data = tokio::fs::read(&path)
+///
+/// The entries of a zip archive that `parser` supports are merged into one model with
+/// a part per entry. Formats are detected with `parsers`, and a zip is only unpacked
+/// when neither `parser` nor `parsers` claim it as a format of its own.
+pub async fn parse_file<P: AsRef<Path>>(
+    parser: &dyn FileParser,
+    path: P,
+    parsers: &ParserRegistry,
+) -> Result<CadModel> {
+    parse_file_with_options(parser, path, parsers, &ParseOptions::default())
+        .await
+        .map(|(model, _)| model)
+}
+
+/// Like [`parse_file`], with explicit parse options
+pub async fn parse_file_with_options<P: AsRef<Path>>(
+    parser: &dyn FileParser,
+    path: P,
+    parsers: &ParserRegistry,
+    options: &ParseOptions,
+) -> Result<(CadModel, ParseReport)> {
+    let path = path.as_ref();
+    let (data, file_name) = read_file(path, options).await?;
+
+    // Custom formats have no magic bytes, one stored as a zip is still the parser's
+    let custom_formats: Vec<FileFormat> = parser
+        .supported_formats()
+        .iter()
+        .filter(|format| matches!(format, FileFormat::Custom(_)))
+        .copied()
+        .collect();
+    let claimed = FileFormat::find_in_path(path, &custom_formats).is_some();
+
+    let entries = match claimed {
+        true => None,
+        false => archive_entries(&data, path, parsers, options)?,
+    };
+    let Some(entries) = entries else {
+        return parser.parse_data_at(&data, file_name, path, options);
+    };
+
+    let mut models = Vec::new();
+    let mut report = ParseReport::default();
+    for entry in entries {
+        let supported = FileFormat::detect(&entry.data, &entry.path)
+            .is_ok_and(|format| parser.supported_formats().contains(&format));
+        if !supported {
+            continue;
+        }
+
+        let (model, entry_report) = parser
+            .parse_data_with_options(&entry.data, entry.file_name, options)
+            .map_err(|error| CadStreamError::ArchiveEntry {
+                entry: entry.path,
+                error: Box::new(error),
+            })?;
+        models.push(model);
+        report.warnings.extend(entry_report.warnings);
+    }
+
+    Ok((CadModel::merge(file_name, models), report))
+}
+
+/// Read and parse every model in `path`, detecting formats from the content
+///
+/// Compressed files are unwrapped and each supported entry of a zip archive becomes
+/// its own model.
+pub async fn load_file(
+    path: &Path,
+    parsers: &ParserRegistry,
+    options: &ParseOptions,
+) -> Result<Vec<LoadedModel>> {
+    let (data, file_name) = read_file(path, options).await?;
+    load_data(&data, file_name, path, parsers, options)
+}
+
+/// Like [`load_file`], for data already read (and decompressed) by [`read_file`]
+pub fn load_data(
+    data: &[u8],
+    file_name: String,
+    path: &Path,
+    parsers: &ParserRegistry,
+    options: &ParseOptions,
+) -> Result<Vec<LoadedModel>> {
+    let Some(entries) = archive_entries(data, path, parsers, options)? else {
+        return Ok(vec![load_model(data, file_name, path, parsers, options)?]);
+    };
+
+    entries
+        .into_iter()
+        .map(|entry| {
+            let entry_path = path.join(&entry.path);
+            load_model(&entry.data, entry.file_name, &entry_path, parsers, options).map_err(
+                |error| CadStreamError::ArchiveEntry {
+                    entry: entry.path,
+                    error: Box::new(error),
+                },
+            )
+        })
+        .collect()
+}
+
+fn load_model(
+    data: &[u8],
+    file_name: String,
+    path: &Path,
+    parsers: &ParserRegistry,
+    options: &ParseOptions,
+) -> Result<LoadedModel> {
+    let parser = parsers.parser_for_data(data, path)?;
+    let (model, report) = parser.parse_data_at(data, file_name, path, options)?;
+
+    Ok(LoadedModel {
+        path: path.to_path_buf(),
+        model,
+        report,
+        parser_name: parser.parser_name(),
+    })
+}
+
+/// The supported entries of `data` if it is a zip archive of parts, rather than a
+/// zip-based model format
+fn archive_entries(
+    data: &[u8],
+    path: &Path,
+    parsers: &ParserRegistry,
+    options: &ParseOptions,
+) -> Result<Option<Vec<ArchiveEntry>>> {
+    if !archive::is_zip(data) || parsers.detect(data, path).is_ok() {
+        return Ok(None);
+    }
+
+    let entries = archive::zip_entries(data, options.decompressed_size_limit(), |entry| {
+        parsers.format_for_path(entry).is_some()
+    })?;
+    if entries.is_empty() {
+        return Err(CadStreamError::UnsupportedFormat(
+            "zip archive without supported CAD files".to_string(),
+        ));
+    }
+    Ok(Some(entries))
+}
+
+/// Read a whole file, returning its contents and file name
+///
+/// Gzip and zstd compressed files are decompressed up to the size limit of `options`,
+/// and the compression extension dropped from the name.
+pub async fn read_file(path: &Path, options: &ParseOptions) -> Result<(Vec<u8>, String)> {
+    let data = tokio::fs::read(path)
         .await
-        .with_context(|| format!("Failed to read file: {}", path.as_ref().display()))?;
+        .map_err(|error| CadStreamError::io(path, error))?;
 
     let file_name = path
-        .as_ref()
         .file_name()
         .and_then(|n| n.to_str())
         .unwrap_or("unknown")
         .to_string();
 
-    parser.parse_data(&data, file_name)
+    archive::decompress(data, file_name, options.decompressed_size_limit())
 }
 
 /// Factory for creating appropriate parsers based on file format and feature flags
+///
+/// Only knows the compiled-in parsers, use a [`ParserRegistry`] to add parsers at
+/// runtime.
 pub struct ParserFactory;
 
 impl ParserFactory {
     /// Create a parser for the given file format using available implementations
     pub fn create_parser(format: FileFormat) -> Result<Box<dyn FileParser>> {
+        Self::create_parser_with(format, StlBackend::Auto)
+    }
+
+    /// Create a parser for the given file format, using `stl_backend` for STL files
+    pub fn create_parser_with(
+        format: FileFormat,
+        stl_backend: StlBackend,
+    ) -> Result<Box<dyn FileParser>> {
         match format {
-            FileFormat::Stl => Self::create_stl_parser(),
+            FileFormat::Stl => Self::create_stl_parser(stl_backend),
+            FileFormat::Obj => Self::create_obj_parser(),
+            FileFormat::Ply => Self::create_ply_parser(),
+            FileFormat::Gltf => Self::create_gltf_parser(),
+            FileFormat::ThreeMf => Self::create_threemf_parser(),
             // Future: add other formats here
+            FileFormat::Custom(extension) => Err(CadStreamError::ParserUnavailable(format!(
+                "No built-in parser for .{} files, register one with a ParserRegistry",
+                extension
+            ))),
         }
     }
 
-    /// Create the best available STL parser based on enabled features
-    fn create_stl_parser() -> Result<Box<dyn FileParser>> {
+    /// Create a parser for `path` based on its file name
+    pub fn create_parser_for_path(
+        path: &Path,
+        stl_backend: StlBackend,
+    ) -> Result<Box<dyn FileParser>> {
+        let format = FileFormat::from_path(path)
+            .ok_or_else(|| CadStreamError::UnsupportedFormat(format!("{}", path.display())))?;
+
+        Self::create_parser_with(format, stl_backend)
+    }
+
+    /// Create a parser for file contents, see [`FileFormat::detect`]
+    pub fn create_parser_for_data(
+        data: &[u8],
+        path: &Path,
+        stl_backend: StlBackend,
+    ) -> Result<Box<dyn FileParser>> {
+        Self::create_parser_with(FileFormat::detect(data, path)?, stl_backend)
+    }
+
+    /// Create the requested STL parser, or the best available one for `Auto`
+    fn create_stl_parser(backend: StlBackend) -> Result<Box<dyn FileParser>> {
+        match backend {
+            StlBackend::Custom => Self::create_custom_stl_parser(),
+            StlBackend::StlIo => Self::create_stl_io_parser(),
+            StlBackend::Auto => {
+                if cfg!(feature = "stl-io-parser") {
+                    Self::create_stl_io_parser()
+                } else if cfg!(feature = "custom-stl-parser") {
+                    Self::create_custom_stl_parser()
+                } else {
+                    Err(CadStreamError::ParserUnavailable("No STL parser implementation available. Enable either 'stl-io-parser' or 'custom-stl-parser' feature.".to_string()))
+                }
+            }
+        }
+    }
+
+    fn create_stl_io_parser() -> Result<Box<dyn FileParser>> {
         #[cfg(feature = "stl-io-parser")]
         {
-            log::info!("Using stl_io parser for STL files");
             Ok(Box::new(crate::parsers::stl_io_parser::StlIoParser::new()))
         }
 
-        #[cfg(all(feature = "custom-stl-parser", not(feature = "stl-io-parser")))]
+        #[cfg(not(feature = "stl-io-parser"))]
+        {
+            Err(CadStreamError::ParserUnavailable(
+                "The stl_io parser is not available. Enable the 'stl-io-parser' feature."
+                    .to_string(),
+            ))
+        }
+    }
+
+    fn create_custom_stl_parser() -> Result<Box<dyn FileParser>> {
+        #[cfg(feature = "custom-stl-parser")]
         {
-            log::info!("Using custom STL parser for STL files");
             Ok(Box::new(
                 crate::parsers::custom_stl_parser::CustomStlParser::new(),
             ))
         }
 
-        #[cfg(not(any(feature = "stl-io-parser", feature = "custom-stl-parser")))]
+        #[cfg(not(feature = "custom-stl-parser"))]
+        {
+            Err(CadStreamError::ParserUnavailable(
+                "The custom STL parser is not available. Enable the 'custom-stl-parser' feature."
+                    .to_string(),
+            ))
+        }
+    }
+
+    /// Create the OBJ parser if enabled
+    fn create_obj_parser() -> Result<Box<dyn FileParser>> {
+        #[cfg(feature = "obj-parser")]
+        {
+            Ok(Box::new(crate::parsers::obj_parser::ObjParser::new()))
+        }
+
+        #[cfg(not(feature = "obj-parser"))]
+        {
+            Err(CadStreamError::ParserUnavailable(
+                "No OBJ parser implementation available. Enable the 'obj-parser' feature."
+                    .to_string(),
+            ))
+        }
+    }
+
+    /// Create the PLY parser if enabled
+    fn create_ply_parser() -> Result<Box<dyn FileParser>> {
+        #[cfg(feature = "ply-parser")]
+        {
+            Ok(Box::new(crate::parsers::ply_parser::PlyParser::new()))
+        }
+
+        #[cfg(not(feature = "ply-parser"))]
+        {
+            Err(CadStreamError::ParserUnavailable(
+                "No PLY parser implementation available. Enable the 'ply-parser' feature."
+                    .to_string(),
+            ))
+        }
+    }
+
+    /// Create the glTF parser if enabled
+    fn create_gltf_parser() -> Result<Box<dyn FileParser>> {
+        #[cfg(feature = "gltf-parser")]
+        {
+            Ok(Box::new(crate::parsers::gltf_parser::GltfParser::new()))
+        }
+
+        #[cfg(not(feature = "gltf-parser"))]
+        {
+            Err(CadStreamError::ParserUnavailable(
+                "No glTF parser implementation available. Enable the 'gltf-parser' feature."
+                    .to_string(),
+            ))
+        }
+    }
+
+    /// Create the 3MF parser if enabled
+    fn create_threemf_parser() -> Result<Box<dyn FileParser>> {
+        #[cfg(feature = "3mf-parser")]
+        {
+            Ok(Box::new(
+                crate::parsers::threemf_parser::ThreeMfParser::new(),
+            ))
+        }
+
+        #[cfg(not(feature = "3mf-parser"))]
         {
-            anyhow::bail!("No STL parser implementation available. Enable either 'stl-io-parser' or 'custom-stl-parser' feature.")
+            Err(CadStreamError::ParserUnavailable(
+                "No 3MF parser implementation available. Enable the '3mf-parser' feature."
+                    .to_string(),
+            ))
         }
     }
 
@@ -107,6 +635,26 @@ impl ParserFactory {
             extensions.extend_from_slice(FileFormat::Stl.extensions());
         }
 
+        #[cfg(feature = "obj-parser")]
+        {
+            extensions.extend_from_slice(FileFormat::Obj.extensions());
+        }
+
+        #[cfg(feature = "ply-parser")]
+        {
+            extensions.extend_from_slice(FileFormat::Ply.extensions());
+        }
+
+        #[cfg(feature = "gltf-parser")]
+        {
+            extensions.extend_from_slice(FileFormat::Gltf.extensions());
+        }
+
+        #[cfg(feature = "3mf-parser")]
+        {
+            extensions.extend_from_slice(FileFormat::ThreeMf.extensions());
+        }
+
         // Future: add other format extensions here
 
         extensions
@@ -114,8 +662,112 @@ impl ParserFactory {
 }
 
 // Sub-modules for different parser implementations
+pub mod registry;
+pub use registry::ParserRegistry;
+
 #[cfg(feature = "custom-stl-parser")]
 pub mod custom_stl_parser;
 
 #[cfg(feature = "stl-io-parser")]
 pub mod stl_io_parser;
+
+#[cfg(feature = "obj-parser")]
+pub mod obj_parser;
+
+#[cfg(feature = "ply-parser")]
+pub mod ply_parser;
+
+#[cfg(feature = "gltf-parser")]
+pub mod gltf_parser;
+
+#[cfg(feature = "3mf-parser")]
+pub mod threemf_parser;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_format_from_content() {
+        let detect = |bytes: &[u8], name: &str| FileFormat::detect(bytes, Path::new(name));
+
+        assert_eq!(
+            detect(b"ply\nformat ascii 1.0\n", "scan").unwrap(),
+            FileFormat::Ply
+        );
+        assert_eq!(
+            detect(b"solid part\nendsolid\n", "upload").unwrap(),
+            FileFormat::Stl
+        );
+        assert_eq!(
+            detect(b"# exported\nv 0 0 0\n", "mesh.txt").unwrap(),
+            FileFormat::Obj
+        );
+
+        // Binary STL whose header starts with "solid", labelled as OBJ
+        let mut binary = b"solid exported".to_vec();
+        binary.resize(80, b' ');
+        binary.extend_from_slice(&1u32.to_le_bytes());
+        binary.extend_from_slice(&[0; 50]);
+        assert_eq!(detect(&binary, "wrong.obj").unwrap(), FileFormat::Stl);
+
+        // The extension only decides when the content is ambiguous
+        assert_eq!(detect(&[0; 40], "part.STL.tmp").unwrap(), FileFormat::Stl);
+        assert!(matches!(
+            detect(b"ISO-10303-21;\nHEADER;", "part.stl"),
+            Err(CadStreamError::UnsupportedFormat(format)) if format == "STEP"
+        ));
+        assert!(detect(&[0; 40], "blob").is_err());
+
+        let mut zip = b"PK\x03\x04".to_vec();
+        assert!(detect(&zip, "parts.zip").is_err());
+        zip.extend_from_slice(b"3D/3dmodel.model");
+        assert_eq!(detect(&zip, "upload").unwrap(), FileFormat::ThreeMf);
+    }
+
+    /// A custom format stored as a zip archive
+    struct ZippedParser;
+
+    impl FileParser for ZippedParser {
+        fn parse_data(&self, data: &[u8], name: String) -> Result<CadModel> {
+            match data.starts_with(b"PK\x03\x04") {
+                true => Ok(CadModel::new(name, Vec::new())),
+                false => Err(CadStreamError::malformed("expected the whole archive")),
+            }
+        }
+
+        fn supported_formats(&self) -> &[FileFormat] {
+            &[FileFormat::Custom("zipped")]
+        }
+
+        fn parser_name(&self) -> &'static str {
+            "Zipped"
+        }
+    }
+
+    #[tokio::test]
+    async fn test_custom_zip_format_is_not_unpacked() {
+        use std::io::Write;
+
+        let dir = std::env::temp_dir().join(format!("cadstream_custom_zip_{}", std::process::id()));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let path = dir.join("bracket.zipped");
+
+        let mut writer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        writer
+            .start_file("a.stl", zip::write::SimpleFileOptions::default())
+            .unwrap();
+        writer.write_all(b"solid a\nendsolid a\n").unwrap();
+        tokio::fs::write(&path, writer.finish().unwrap().into_inner())
+            .await
+            .unwrap();
+
+        let mut parsers = ParserRegistry::new();
+        parsers.register(ZippedParser, registry::BUILTIN_PRIORITY);
+        let parser = parsers.parser_for(FileFormat::Custom("zipped")).unwrap();
+        let model = parse_file(parser.as_ref(), &path, &parsers).await.unwrap();
+        assert_eq!(model.name, "bracket.zipped");
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
@@ -1,13 +1,19 @@
-use super::{FileFormat, FileParser};
+use super::{FileFormat, FileParser, ParseOptions, ParseReport};
 use crate::cad_data::CadModel;
+use crate::error::Result;
 use crate::stl_parser::StlParser;
-use anyhow::Result;
 
 /// Custom STL parser implementation using our own parsing logic
 pub struct CustomStlParser {
     parser: StlParser,
 }
 
+impl Default for CustomStlParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl CustomStlParser {
     pub fn new() -> Self {
         Self {
@@ -21,6 +27,15 @@ impl FileParser for CustomStlParser {
         self.parser.parse_data(data, name)
     }
 
+    fn parse_data_with_options(
+        &self,
+        data: &[u8],
+        name: String,
+        options: &ParseOptions,
+    ) -> Result<(CadModel, ParseReport)> {
+        self.parser.parse_data_with_options(data, name, options)
+    }
+
     fn supported_formats(&self) -> &[FileFormat] {
         &[FileFormat::Stl]
     }
@@ -0,0 +1,603 @@
+use super::{FileFormat, FileParser, ParseOptions, ParseReport};
+use crate::cad_data::{CadModel, MeshPart, Rgba, Triangle};
+use crate::error::{CadStreamError, Result};
+use base64::Engine;
+use glam::{Mat4, Vec3, Vec4};
+use gltf::mesh::Mode;
+use gltf::{buffer, Gltf, Node, Primitive};
+use std::borrow::Cow;
+use std::path::{Component, Path, PathBuf};
+
+/// glTF 2.0 parser for `.gltf` files, with data URI or external buffers, and `.glb`
+///
+/// Meshes are placed by walking the node hierarchy of the default scene, each node
+/// with a mesh becoming a part. Triangle, strip and fan primitives are flattened into
+/// triangles colored with their material's base color. Textures, skins, morph targets
+/// and point or line primitives are ignored.
+pub struct GltfParser;
+
+impl Default for GltfParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Triangles gathered while walking the scene
+#[derive(Default)]
+struct SceneMesh {
+    triangles: Vec<Triangle>,
+    colors: Vec<[Rgba; 3]>,
+    parts: Vec<MeshPart>,
+    /// Whether any primitive has a non-white base color or vertex colors
+    colored: bool,
+    /// Nodes visited so far, which bounds the work even for nodes without meshes
+    instances: usize,
+    /// Most triangles and node instances the scene may expand to
+    max_triangles: usize,
+}
+
+impl GltfParser {
+    pub fn new() -> Self {
+        Self
+    }
+
+    fn parse_model(
+        &self,
+        data: &[u8],
+        name: String,
+        base_dir: Option<&Path>,
+        options: &ParseOptions,
+    ) -> Result<CadModel> {
+        let mesh = self.parse_gltf(data, base_dir, options)?;
+
+        println!(
+            "📐 Parsed {} triangles in {} nodes from {}",
+            mesh.triangles.len(),
+            mesh.parts.len(),
+            name
+        );
+
+        let mut model = CadModel::new(name, mesh.triangles);
+        model.parts = mesh.parts;
+        model.vertex_colors = mesh.colored.then_some(mesh.colors);
+        model.precision_info.file_size_bytes = data.len();
+
+        Ok(model)
+    }
+
+    /// Parse a glTF or GLB, resolving external buffers relative to `base_dir`
+    fn parse_gltf(
+        &self,
+        data: &[u8],
+        base_dir: Option<&Path>,
+        options: &ParseOptions,
+    ) -> Result<SceneMesh> {
+        let gltf = Gltf::from_slice(data)
+            .map_err(|error| CadStreamError::malformed(format!("Invalid glTF: {}", error)))?;
+        let buffers = gltf
+            .buffers()
+            .map(|buffer| self.load_buffer(&buffer, gltf.blob.as_deref(), base_dir))
+            .collect::<Result<Vec<_>>>()?;
+
+        // Without scenes every node that isn't a child of another one is a root
+        let roots: Vec<Node> = match gltf.default_scene().or_else(|| gltf.scenes().next()) {
+            Some(scene) => scene.nodes().collect(),
+            None => {
+                let children: Vec<usize> = gltf
+                    .nodes()
+                    .flat_map(|node| node.children().map(|child| child.index()))
+                    .collect();
+                gltf.nodes()
+                    .filter(|node| !children.contains(&node.index()))
+                    .collect()
+            }
+        };
+
+        let mut mesh = SceneMesh {
+            max_triangles: options.triangle_limit(),
+            ..Default::default()
+        };
+        for node in roots {
+            self.visit_node(
+                &node,
+                Mat4::IDENTITY,
+                &buffers,
+                &mut mesh,
+                gltf.nodes().len(),
+            )?;
+        }
+        Ok(mesh)
+    }
+
+    fn load_buffer<'a>(
+        &self,
+        buffer: &gltf::Buffer,
+        blob: Option<&'a [u8]>,
+        base_dir: Option<&Path>,
+    ) -> Result<Cow<'a, [u8]>> {
+        let data = match buffer.source() {
+            buffer::Source::Bin => Cow::Borrowed(
+                blob.ok_or_else(|| CadStreamError::malformed("GLB has no binary chunk"))?,
+            ),
+            buffer::Source::Uri(uri) => Cow::Owned(self.load_uri(uri, base_dir)?),
+        };
+
+        if data.len() < buffer.length() {
+            return Err(CadStreamError::truncated(format!(
+                "buffer {} has {} of {} bytes",
+                buffer.index(),
+                data.len(),
+                buffer.length()
+            )));
+        }
+        Ok(data)
+    }
+
+    fn load_uri(&self, uri: &str, base_dir: Option<&Path>) -> Result<Vec<u8>> {
+        if let Some(data_uri) = uri.strip_prefix("data:") {
+            let (_, encoded) = data_uri.split_once(";base64,").ok_or_else(|| {
+                CadStreamError::malformed("Only base64 data URIs are supported for buffers")
+            })?;
+            return base64::engine::general_purpose::STANDARD
+                .decode(encoded)
+                .map_err(|error| {
+                    CadStreamError::malformed(format!("Invalid base64 buffer: {}", error))
+                });
+        }
+
+        let base_dir = base_dir.ok_or_else(|| {
+            CadStreamError::malformed(format!(
+                "External buffer '{}' can only be resolved when loading from a file",
+                uri
+            ))
+        })?;
+        let path = base_dir.join(relative_buffer_path(uri)?);
+        std::fs::read(&path).map_err(|error| CadStreamError::io(path, error))
+    }
+
+    fn visit_node(
+        &self,
+        node: &Node,
+        parent: Mat4,
+        buffers: &[Cow<[u8]>],
+        mesh: &mut SceneMesh,
+        max_depth: usize,
+    ) -> Result<()> {
+        // A valid hierarchy is a forest, nesting deeper than the node count means a cycle
+        let Some(max_depth) = max_depth.checked_sub(1) else {
+            return Err(CadStreamError::malformed("Node hierarchy contains a cycle"));
+        };
+
+        // Nodes may be shared by several parents, which lets a small file expand
+        // exponentially
+        mesh.instances += 1;
+        if mesh.instances > mesh.max_triangles {
+            return Err(too_many_triangles(mesh.max_triangles));
+        }
+
+        let transform = parent * Mat4::from_cols_array_2d(&node.transform().matrix());
+
+        if let Some(node_mesh) = node.mesh() {
+            let start = mesh.triangles.len();
+            for primitive in node_mesh.primitives() {
+                self.add_primitive(&primitive, transform, buffers, mesh)?;
+            }
+
+            let name = node
+                .name()
+                .or(node_mesh.name())
+                .map_or_else(|| format!("node {}", node.index()), str::to_string);
+            mesh.parts.push(MeshPart {
+                name,
+                triangles: start..mesh.triangles.len(),
+            });
+        }
+
+        for child in node.children() {
+            self.visit_node(&child, transform, buffers, mesh, max_depth)?;
+        }
+        Ok(())
+    }
+
+    fn add_primitive(
+        &self,
+        primitive: &Primitive,
+        transform: Mat4,
+        buffers: &[Cow<[u8]>],
+        mesh: &mut SceneMesh,
+    ) -> Result<()> {
+        let mode = primitive.mode();
+        if !matches!(
+            mode,
+            Mode::Triangles | Mode::TriangleStrip | Mode::TriangleFan
+        ) {
+            return Ok(());
+        }
+
+        let reader = primitive.reader(|buffer| buffers.get(buffer.index()).map(|data| &data[..]));
+        let positions: Vec<Vec3> = reader
+            .read_positions()
+            .ok_or_else(|| CadStreamError::malformed("Primitive has no readable POSITION"))?
+            .map(|position| transform.transform_point3(Vec3::from(position)))
+            .collect();
+        let indices: Vec<u32> = match reader.read_indices() {
+            Some(indices) => indices.into_u32().collect(),
+            None => (0..positions.len() as u32).collect(),
+        };
+        let vertex_colors: Option<Vec<Vec4>> = reader
+            .read_colors(0)
+            .map(|colors| colors.into_rgba_f32().map(Vec4::from).collect());
+
+        let base_color = Vec4::from(
+            primitive
+                .material()
+                .pbr_metallic_roughness()
+                .base_color_factor(),
+        );
+        mesh.colored |= base_color != Vec4::ONE || vertex_colors.is_some();
+
+        let faces: Vec<[u32; 3]> = match mode {
+            Mode::TriangleStrip => indices
+                .windows(3)
+                .enumerate()
+                .map(|(i, w)| {
+                    if i % 2 == 0 {
+                        [w[0], w[1], w[2]]
+                    } else {
+                        [w[1], w[0], w[2]]
+                    }
+                })
+                .collect(),
+            Mode::TriangleFan => (1..indices.len().saturating_sub(1))
+                .map(|i| [indices[0], indices[i], indices[i + 1]])
+                .collect(),
+            _ => indices
+                .chunks_exact(3)
+                .map(|face| [face[0], face[1], face[2]])
+                .collect(),
+        };
+
+        if mesh.triangles.len() + faces.len() > mesh.max_triangles {
+            return Err(too_many_triangles(mesh.max_triangles));
+        }
+
+        // Mirroring transforms turn the winding inside out
+        let mirrored = transform.determinant() < 0.0;
+
+        for mut face in faces {
+            if mirrored {
+                face.swap(1, 2);
+            }
+
+            let mut vertices = [Vec3::ZERO; 3];
+            for (vertex, &index) in vertices.iter_mut().zip(&face) {
+                *vertex = *positions.get(index as usize).ok_or_else(|| {
+                    CadStreamError::malformed(format!(
+                        "Index {} is out of range for {} vertices",
+                        index,
+                        positions.len()
+                    ))
+                })?;
+            }
+            let normal = (vertices[1] - vertices[0])
+                .cross(vertices[2] - vertices[0])
+                .normalize_or_zero();
+            mesh.triangles.push(Triangle { vertices, normal });
+
+            mesh.colors.push(face.map(|index| {
+                let vertex_color = vertex_colors
+                    .as_ref()
+                    .and_then(|colors| colors.get(index as usize).copied())
+                    .unwrap_or(Vec4::ONE);
+                linear_to_rgba(base_color * vertex_color)
+            }));
+        }
+
+        Ok(())
+    }
+}
+
+fn too_many_triangles(max_triangles: usize) -> CadStreamError {
+    CadStreamError::malformed(format!(
+        "Scene expands to more than {} triangles or node instances",
+        max_triangles
+    ))
+}
+
+/// glTF colors are linear, model colors are 8-bit sRGB
+fn linear_to_rgba(color: Vec4) -> Rgba {
+    let encode = |linear: f32| {
+        let linear = linear.clamp(0.0, 1.0);
+        let srgb = if linear <= 0.003_130_8 {
+            linear * 12.92
+        } else {
+            1.055 * linear.powf(1.0 / 2.4) - 0.055
+        };
+        (srgb * 255.0).round() as u8
+    };
+    [
+        encode(color.x),
+        encode(color.y),
+        encode(color.z),
+        (color.w.clamp(0.0, 1.0) * 255.0).round() as u8,
+    ]
+}
+
+/// The path of an external buffer relative to the glTF file
+///
+/// Only plain relative paths are followed, a file must not make the loader read
+/// `/etc/passwd`, `../secret.bin` or anything over the network.
+fn relative_buffer_path(uri: &str) -> Result<PathBuf> {
+    // `scheme:` as in RFC 3986, which also catches `file:` and drive letters
+    let has_scheme = uri.split_once(':').is_some_and(|(scheme, _)| {
+        scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+            && scheme
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+    });
+    if has_scheme {
+        return Err(CadStreamError::malformed(format!(
+            "External buffer '{}' has an unsupported URI scheme",
+            uri
+        )));
+    }
+
+    let path = PathBuf::from(percent_decode(uri));
+    let inside = path
+        .components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+    if !inside || path.as_os_str().is_empty() {
+        return Err(CadStreamError::malformed(format!(
+            "External buffer '{}' must be a relative path below the glTF file",
+            uri
+        )));
+    }
+    Ok(path)
+}
+
+/// Undo `%XX` escapes in a relative URI
+fn percent_decode(uri: &str) -> String {
+    let bytes = uri.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .filter(|_| bytes[i] == b'%')
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+impl FileParser for GltfParser {
+    fn parse_data(&self, data: &[u8], name: String) -> Result<CadModel> {
+        self.parse_model(data, name, None, &ParseOptions::default())
+    }
+
+    /// Strict and lenient mode are the same, but the options set the size limit
+    fn parse_data_with_options(
+        &self,
+        data: &[u8],
+        name: String,
+        options: &ParseOptions,
+    ) -> Result<(CadModel, ParseReport)> {
+        let model = self.parse_model(data, name, None, options)?;
+        Ok((model, ParseReport::default()))
+    }
+
+    fn parse_data_at(
+        &self,
+        data: &[u8],
+        name: String,
+        path: &Path,
+        options: &ParseOptions,
+    ) -> Result<(CadModel, ParseReport)> {
+        let base_dir = path.parent().unwrap_or(Path::new(""));
+        let model = self.parse_model(data, name, Some(base_dir), options)?;
+        Ok((model, ParseReport::default()))
+    }
+
+    fn supported_formats(&self) -> &[FileFormat] {
+        &[FileFormat::Gltf]
+    }
+
+    fn parser_name(&self) -> &'static str {
+        "glTF Parser"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// One triangle, indexed with u16, in a parent node translated by 10 along X and
+    /// a child scaled by 2, with a red material
+    fn sample(buffer_uri: Option<&str>) -> (String, Vec<u8>) {
+        let mut buffer = Vec::new();
+        for value in [0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0] {
+            buffer.extend_from_slice(&value.to_le_bytes());
+        }
+        for index in [0u16, 1, 2, 0] {
+            buffer.extend_from_slice(&index.to_le_bytes());
+        }
+
+        let uri = match buffer_uri {
+            Some(uri) => format!(r#""uri": "{}","#, uri),
+            None => String::new(),
+        };
+        let json = format!(
+            r#"{{
+  "asset": {{"version": "2.0"}},
+  "scene": 0,
+  "scenes": [{{"nodes": [0]}}],
+  "nodes": [
+    {{"name": "assembly", "translation": [10, 0, 0], "children": [1]}},
+    {{"name": "bracket", "scale": [2, 2, 2], "mesh": 0}}
+  ],
+  "meshes": [{{"primitives": [{{"attributes": {{"POSITION": 0}}, "indices": 1, "material": 0}}]}}],
+  "materials": [{{"pbrMetallicRoughness": {{"baseColorFactor": [1, 0, 0, 1]}}}}],
+  "buffers": [{{{} "byteLength": {}}}],
+  "bufferViews": [
+    {{"buffer": 0, "byteOffset": 0, "byteLength": 36}},
+    {{"buffer": 0, "byteOffset": 36, "byteLength": 6}}
+  ],
+  "accessors": [
+    {{"bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3", "min": [0, 0, 0], "max": [1, 1, 0]}},
+    {{"bufferView": 1, "componentType": 5123, "count": 3, "type": "SCALAR"}}
+  ]
+}}"#,
+            uri,
+            buffer.len()
+        );
+        (json, buffer)
+    }
+
+    fn assert_sample_model(model: &CadModel) {
+        assert_eq!(model.triangles.len(), 1);
+        assert_eq!(
+            model.triangles[0].vertices,
+            [
+                Vec3::new(10.0, 0.0, 0.0),
+                Vec3::new(12.0, 0.0, 0.0),
+                Vec3::new(10.0, 2.0, 0.0)
+            ]
+        );
+        assert_eq!(model.triangles[0].normal, Vec3::Z);
+        assert_eq!(model.parts[0].name, "bracket");
+        assert_eq!(model.vertex_colors, Some(vec![[[255, 0, 0, 255]; 3]]));
+    }
+
+    #[test]
+    fn test_gltf_with_data_uri_and_glb() {
+        let parser = GltfParser::new();
+
+        let (_, buffer) = sample(None);
+        let data_uri = format!(
+            "data:application/octet-stream;base64,{}",
+            base64::engine::general_purpose::STANDARD.encode(&buffer)
+        );
+        let (json, _) = sample(Some(&data_uri));
+        let model = parser
+            .parse_data(json.as_bytes(), "part.gltf".to_string())
+            .unwrap();
+        assert_sample_model(&model);
+
+        // GLB: header, then a JSON and a BIN chunk, each padded to 4 bytes
+        let (mut json, mut buffer) = sample(None);
+        while json.len() % 4 != 0 {
+            json.push(' ');
+        }
+        buffer.resize(buffer.len().next_multiple_of(4), 0);
+        let mut glb = Vec::new();
+        glb.extend_from_slice(b"glTF");
+        glb.extend_from_slice(&2u32.to_le_bytes());
+        glb.extend_from_slice(&((12 + 8 + json.len() + 8 + buffer.len()) as u32).to_le_bytes());
+        glb.extend_from_slice(&(json.len() as u32).to_le_bytes());
+        glb.extend_from_slice(b"JSON");
+        glb.extend_from_slice(json.as_bytes());
+        glb.extend_from_slice(&(buffer.len() as u32).to_le_bytes());
+        glb.extend_from_slice(b"BIN\0");
+        glb.extend_from_slice(&buffer);
+
+        assert_eq!(
+            FileFormat::detect(&glb, Path::new("upload")).unwrap(),
+            FileFormat::Gltf
+        );
+        let model = parser.parse_data(&glb, "part.glb".to_string()).unwrap();
+        assert_sample_model(&model);
+    }
+
+    #[test]
+    fn test_shared_nodes_expansion_is_limited() {
+        // Both nodes of each level have both nodes of the level below as children,
+        // placing the triangle 2^31 times
+        let mut nodes = vec![r#"{"mesh": 0}"#.to_string(); 2];
+        for level in 1..=30 {
+            let children = format!(r#"{{"children": [{}, {}]}}"#, 2 * level - 2, 2 * level - 1);
+            nodes.extend([children.clone(), children]);
+        }
+        let (_, buffer) = sample(None);
+        let json = format!(
+            r#"{{
+  "asset": {{"version": "2.0"}},
+  "scenes": [{{"nodes": [60]}}],
+  "nodes": [{}],
+  "meshes": [{{"primitives": [{{"attributes": {{"POSITION": 0}}}}]}}],
+  "buffers": [{{"uri": "data:application/octet-stream;base64,{}", "byteLength": {}}}],
+  "bufferViews": [{{"buffer": 0, "byteOffset": 0, "byteLength": 36}}],
+  "accessors": [
+    {{"bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3", "min": [0, 0, 0], "max": [1, 1, 0]}}
+  ]
+}}"#,
+            nodes.join(", "),
+            base64::engine::general_purpose::STANDARD.encode(&buffer),
+            buffer.len()
+        );
+
+        let options = ParseOptions {
+            max_decompressed_size: Some(1 << 20),
+            ..Default::default()
+        };
+        let error = GltfParser::new()
+            .parse_data_with_options(json.as_bytes(), "bomb.gltf".to_string(), &options)
+            .unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("Scene expands to more than 20971 triangles"));
+    }
+
+    #[test]
+    fn test_external_buffer_resolved_next_to_file() {
+        let dir = std::env::temp_dir().join(format!("cadstream_gltf_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (json, buffer) = sample(Some("part%20data.bin"));
+        std::fs::write(dir.join("part data.bin"), buffer).unwrap();
+
+        let parser = GltfParser::new();
+        assert!(parser
+            .parse_data(json.as_bytes(), "part.gltf".to_string())
+            .is_err());
+        let (model, _) = parser
+            .parse_data_at(
+                json.as_bytes(),
+                "part.gltf".to_string(),
+                &dir.join("part.gltf"),
+                &ParseOptions::default(),
+            )
+            .unwrap();
+        assert_sample_model(&model);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_buffer_uri_must_stay_below_the_file() {
+        assert_eq!(
+            relative_buffer_path("sub/part%20data.bin").unwrap(),
+            PathBuf::from("sub/part data.bin")
+        );
+        for uri in [
+            "../secret.bin",
+            "sub/../../secret.bin",
+            "%2E%2E/secret.bin",
+            "/etc/passwd",
+            "%2Fetc/passwd",
+            "file:///etc/passwd",
+            "https://example.com/part.bin",
+            "C:/part.bin",
+            "",
+        ] {
+            assert!(relative_buffer_path(uri).is_err(), "{}", uri);
+        }
+    }
+}
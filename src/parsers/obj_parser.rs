@@ -0,0 +1,325 @@
+use super::{FileFormat, FileParser, SourceLocation};
+use crate::cad_data::{CadModel, IndexedMesh, MeshPart};
+use crate::error::{CadStreamError, Result};
+use glam::Vec3;
+
+/// Wavefront OBJ parser
+///
+/// Reads `v`/`vn`/`vt`/`f` records, fan-triangulates polygon faces and maps
+/// `o`/`g` statements to named parts. Materials, smoothing groups, lines and
+/// points are ignored.
+pub struct ObjParser;
+
+impl Default for ObjParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// One corner of an `f` record, resolved to zero-based indices
+#[derive(Debug, Clone, Copy)]
+struct FaceVertex {
+    position: usize,
+    normal: Option<usize>,
+}
+
+impl ObjParser {
+    pub fn new() -> Self {
+        Self
+    }
+
+    fn parse_obj(&self, content: &str) -> Result<(IndexedMesh, Vec<MeshPart>)> {
+        let mut mesh = IndexedMesh::default();
+        let mut normals: Vec<Vec3> = Vec::new();
+        let mut texcoord_count = 0usize;
+
+        let mut parts = Vec::new();
+        let mut current_group: Option<String> = None;
+        let mut group_start = 0;
+
+        for (line_index, raw_line) in content.lines().enumerate() {
+            let line_number = line_index + 1;
+            let line = match raw_line.find('#') {
+                Some(comment) => &raw_line[..comment],
+                None => raw_line,
+            }
+            .trim();
+            let location = SourceLocation::Line {
+                line: line_number,
+                column: 1 + raw_line.len() - raw_line.trim_start().len(),
+            };
+
+            let mut tokens = line.split_whitespace();
+            let Some(keyword) = tokens.next() else {
+                continue;
+            };
+
+            match keyword {
+                "v" => {
+                    let vertex = self
+                        .parse_vec3(&mut tokens)
+                        .map_err(|error| error.at(location))?;
+                    mesh.positions.push(vertex);
+                }
+                "vn" => {
+                    let normal = self
+                        .parse_vec3(&mut tokens)
+                        .map_err(|error| error.at(location))?;
+                    normals.push(normal);
+                }
+                "vt" => {
+                    // Texture coordinates are validated for indexing but not stored
+                    let token = tokens.next().ok_or_else(|| {
+                        CadStreamError::malformed("Missing texture coordinate").at(location)
+                    })?;
+                    self.parse_f32(token).map_err(|error| error.at(location))?;
+                    texcoord_count += 1;
+                }
+                "f" => {
+                    let corners = tokens
+                        .map(|token| {
+                            self.parse_face_vertex(
+                                token,
+                                mesh.positions.len(),
+                                texcoord_count,
+                                normals.len(),
+                            )
+                        })
+                        .collect::<Result<Vec<_>>>()
+                        .map_err(|error| error.at(location))?;
+
+                    if corners.len() < 3 {
+                        return Err(CadStreamError::malformed("Face has fewer than 3 vertices")
+                            .at(location));
+                    }
+
+                    // Fan-triangulate around the first corner
+                    for i in 1..corners.len() - 1 {
+                        let face = [corners[0], corners[i], corners[i + 1]];
+                        mesh.normals
+                            .push(self.face_normal(&face, &mesh.positions, &normals));
+                        mesh.faces.push(face.map(|corner| corner.position as u32));
+                    }
+                }
+                "o" | "g" => {
+                    let name = tokens.collect::<Vec<_>>().join(" ");
+                    if current_group.is_some() || mesh.faces.len() > group_start {
+                        self.close_group(&mut parts, current_group.take(), group_start, &mesh);
+                    }
+                    current_group = Some(if name.is_empty() {
+                        "default".to_string()
+                    } else {
+                        name
+                    });
+                    group_start = mesh.faces.len();
+                }
+                // Materials, smoothing groups, lines, points and free-form geometry
+                _ => {}
+            }
+        }
+
+        if current_group.is_some() {
+            self.close_group(&mut parts, current_group, group_start, &mesh);
+        }
+
+        Ok((mesh, parts))
+    }
+
+    fn close_group(
+        &self,
+        parts: &mut Vec<MeshPart>,
+        name: Option<String>,
+        start: usize,
+        mesh: &IndexedMesh,
+    ) {
+        // Faces before the first `o`/`g` statement end up in an implicit "default" part,
+        // groups without any faces (e.g. an `o` immediately followed by a `g`) are dropped
+        if mesh.faces.len() > start {
+            parts.push(MeshPart {
+                name: name.unwrap_or_else(|| "default".to_string()),
+                triangles: start..mesh.faces.len(),
+            });
+        }
+    }
+
+    fn parse_vec3<'a>(&self, tokens: &mut impl Iterator<Item = &'a str>) -> Result<Vec3> {
+        let mut component = || -> Result<f32> {
+            let token = tokens
+                .next()
+                .ok_or_else(|| CadStreamError::malformed("Expected 3 components"))?;
+            self.parse_f32(token)
+        };
+
+        Ok(Vec3::new(component()?, component()?, component()?))
+    }
+
+    fn parse_f32(&self, token: &str) -> Result<f32> {
+        token.parse().map_err(|_| CadStreamError::InvalidNumber {
+            location: None,
+            token: token.to_string(),
+        })
+    }
+
+    /// Parse `v`, `v/vt`, `v//vn` or `v/vt/vn`, resolving negative (relative) indices
+    fn parse_face_vertex(
+        &self,
+        token: &str,
+        position_count: usize,
+        texcoord_count: usize,
+        normal_count: usize,
+    ) -> Result<FaceVertex> {
+        let mut fields = token.split('/');
+
+        let position = fields.next().filter(|f| !f.is_empty()).ok_or_else(|| {
+            CadStreamError::malformed(format!("Missing vertex index in '{}'", token))
+        })?;
+        let position = self.resolve_index(position, position_count)?;
+
+        if let Some(texcoord) = fields.next().filter(|f| !f.is_empty()) {
+            self.resolve_index(texcoord, texcoord_count)?;
+        }
+
+        let normal = match fields.next().filter(|f| !f.is_empty()) {
+            Some(normal) => Some(self.resolve_index(normal, normal_count)?),
+            None => None,
+        };
+
+        Ok(FaceVertex { position, normal })
+    }
+
+    fn resolve_index(&self, field: &str, count: usize) -> Result<usize> {
+        let index = field
+            .parse::<i64>()
+            .map_err(|_| CadStreamError::InvalidNumber {
+                location: None,
+                token: field.to_string(),
+            })?;
+
+        let resolved = match index {
+            0 => {
+                return Err(CadStreamError::malformed(
+                    "OBJ indices are 1-based, found 0",
+                ))
+            }
+            i if i > 0 => i - 1,
+            i => count as i64 + i,
+        };
+
+        if resolved < 0 || resolved >= count as i64 {
+            return Err(CadStreamError::malformed(format!(
+                "Index {} out of range ({} elements defined)",
+                index, count
+            )));
+        }
+
+        Ok(resolved as usize)
+    }
+
+    fn face_normal(&self, face: &[FaceVertex; 3], positions: &[Vec3], normals: &[Vec3]) -> Vec3 {
+        let vertices = [
+            positions[face[0].position],
+            positions[face[1].position],
+            positions[face[2].position],
+        ];
+
+        // Prefer the averaged vertex normals, fall back to the geometric face normal
+        let vertex_normal_sum = face
+            .iter()
+            .filter_map(|corner| corner.normal.map(|n| normals[n]))
+            .fold(Vec3::ZERO, |acc, n| acc + n);
+
+        if vertex_normal_sum != Vec3::ZERO {
+            vertex_normal_sum.normalize_or_zero()
+        } else {
+            (vertices[1] - vertices[0])
+                .cross(vertices[2] - vertices[0])
+                .normalize_or_zero()
+        }
+    }
+}
+
+impl FileParser for ObjParser {
+    fn parse_data(&self, data: &[u8], name: String) -> Result<CadModel> {
+        let content = String::from_utf8_lossy(data);
+        let (mesh, parts) = self.parse_obj(&content)?;
+
+        println!(
+            "📐 Parsed {} triangles in {} groups from {}",
+            mesh.faces.len(),
+            parts.len(),
+            name
+        );
+
+        let mut model = CadModel::from_indexed(name, mesh);
+        model.parts = parts;
+        model.precision_info.file_size_bytes = data.len();
+
+        Ok(model)
+    }
+
+    fn supported_formats(&self) -> &[FileFormat] {
+        &[FileFormat::Obj]
+    }
+
+    fn parser_name(&self) -> &'static str {
+        "OBJ Parser"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_quad_with_groups_and_negative_indices() {
+        let obj_data = b"# two quads
+o plate
+v 0.0 0.0 0.0
+v 1.0 0.0 0.0
+v 1.0 1.0 0.0
+v 0.0 1.0 0.0
+vt 0.0 0.0
+vn 0.0 0.0 1.0
+g top
+f 1/1/1 2/1/1 3/1/1 4/1/1
+g bottom
+f -1//1 -2//1 -3//1 -4//1
+";
+
+        let parser = ObjParser::new();
+        let model = parser
+            .parse_data(obj_data, "plate.obj".to_string())
+            .unwrap();
+
+        assert_eq!(model.triangles.len(), 4);
+        assert_eq!(model.triangles[0].vertices[0], Vec3::new(0.0, 0.0, 0.0));
+        assert_eq!(model.triangles[0].vertices[2], Vec3::new(1.0, 1.0, 0.0));
+        assert_eq!(model.triangles[2].vertices[0], Vec3::new(0.0, 1.0, 0.0));
+        assert_eq!(model.triangles[0].normal, Vec3::new(0.0, 0.0, 1.0));
+        assert_eq!(
+            model.parts,
+            vec![
+                MeshPart {
+                    name: "top".to_string(),
+                    triangles: 0..2,
+                },
+                MeshPart {
+                    name: "bottom".to_string(),
+                    triangles: 2..4,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_out_of_range_index_is_rejected() {
+        let obj_data = b"v 0 0 0\nv 1 0 0\nf 1 2 3\n";
+
+        let parser = ObjParser::new();
+        let err = parser
+            .parse_data(obj_data, "broken.obj".to_string())
+            .unwrap_err();
+
+        assert!(format!("{:#}", err).contains("line 3"));
+    }
+}
@@ -0,0 +1,741 @@
+use super::{FileFormat, FileParser, SourceLocation};
+use crate::cad_data::{CadModel, IndexedMesh, Rgba};
+use crate::error::{CadStreamError, Result};
+use glam::Vec3;
+use std::io::{Cursor, Read};
+
+/// Stanford PLY parser
+///
+/// Supports `ascii`, `binary_little_endian` and `binary_big_endian` bodies. Faces
+/// are fan-triangulated and vertex `red`/`green`/`blue`/`alpha` properties are kept
+/// as per-vertex colors. Elements other than `vertex` and `face` are skipped.
+pub struct PlyParser;
+
+impl Default for PlyParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PlyEncoding {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ScalarType {
+    Int8,
+    UInt8,
+    Int16,
+    UInt16,
+    Int32,
+    UInt32,
+    Float32,
+    Float64,
+}
+
+impl ScalarType {
+    fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "char" | "int8" => Ok(ScalarType::Int8),
+            "uchar" | "uint8" => Ok(ScalarType::UInt8),
+            "short" | "int16" => Ok(ScalarType::Int16),
+            "ushort" | "uint16" => Ok(ScalarType::UInt16),
+            "int" | "int32" => Ok(ScalarType::Int32),
+            "uint" | "uint32" => Ok(ScalarType::UInt32),
+            "float" | "float32" => Ok(ScalarType::Float32),
+            "double" | "float64" => Ok(ScalarType::Float64),
+            _ => Err(CadStreamError::malformed(format!(
+                "Unknown PLY property type: {}",
+                name
+            ))),
+        }
+    }
+
+    fn is_float(&self) -> bool {
+        matches!(self, ScalarType::Float32 | ScalarType::Float64)
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            ScalarType::Int8 | ScalarType::UInt8 => 1,
+            ScalarType::Int16 | ScalarType::UInt16 => 2,
+            ScalarType::Int32 | ScalarType::UInt32 | ScalarType::Float32 => 4,
+            ScalarType::Float64 => 8,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum PropertyKind {
+    Scalar(ScalarType),
+    List {
+        count_type: ScalarType,
+        item_type: ScalarType,
+    },
+}
+
+#[derive(Debug, Clone)]
+struct Property {
+    name: String,
+    kind: PropertyKind,
+}
+
+#[derive(Debug, Clone)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+impl Element {
+    fn scalar_index(&self, names: &[&str]) -> Option<usize> {
+        self.properties.iter().position(|p| {
+            matches!(p.kind, PropertyKind::Scalar(_)) && names.contains(&p.name.as_str())
+        })
+    }
+
+    /// How many records to reserve room for, as many as the header declares but no
+    /// more than `body_len` bytes can hold, so a corrupt count can't exhaust memory
+    fn capacity_hint(&self, encoding: PlyEncoding, body_len: usize) -> usize {
+        let min_record_len: usize = self
+            .properties
+            .iter()
+            .map(|property| match (encoding, &property.kind) {
+                // At least one digit and a separator
+                (PlyEncoding::Ascii, _) => 2,
+                (_, PropertyKind::Scalar(ty)) => ty.size(),
+                (_, PropertyKind::List { count_type, .. }) => count_type.size(),
+            })
+            .sum();
+        self.count.min(body_len / min_record_len.max(1))
+    }
+}
+
+#[derive(Debug)]
+struct PlyHeader {
+    encoding: PlyEncoding,
+    elements: Vec<Element>,
+    body_offset: usize,
+}
+
+/// Reads scalar values from either an ASCII or a binary PLY body
+enum BodyReader<'a> {
+    Ascii {
+        tokens: std::str::SplitAsciiWhitespace<'a>,
+        body: &'a str,
+        /// Line of the file the body starts on, for error locations
+        first_line: usize,
+    },
+    Binary {
+        cursor: Cursor<&'a [u8]>,
+        big_endian: bool,
+        /// Offset of the body within the file, for error locations
+        body_offset: usize,
+    },
+}
+
+impl BodyReader<'_> {
+    /// Offset into the body of the next value, cheap enough to take for every record
+    fn position(&self) -> usize {
+        match self {
+            BodyReader::Ascii { tokens, body, .. } => {
+                tokens.clone().next().map_or(body.len(), |token| {
+                    token.as_ptr() as usize - body.as_ptr() as usize
+                })
+            }
+            BodyReader::Binary { cursor, .. } => cursor.position() as usize,
+        }
+    }
+
+    /// Where in the file a [`BodyReader::position`] is
+    fn location(&self, position: usize) -> SourceLocation {
+        match self {
+            BodyReader::Ascii {
+                body, first_line, ..
+            } => {
+                let before = &body[..position];
+                let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+                SourceLocation::Line {
+                    line: first_line + before.matches('\n').count(),
+                    column: position - line_start + 1,
+                }
+            }
+            BodyReader::Binary { body_offset, .. } => {
+                SourceLocation::ByteOffset(body_offset + position)
+            }
+        }
+    }
+
+    fn read(&mut self, ty: ScalarType) -> Result<f64> {
+        match self {
+            BodyReader::Ascii { tokens, .. } => {
+                let token = tokens
+                    .next()
+                    .ok_or_else(|| CadStreamError::truncated("PLY body ended unexpectedly"))?;
+                let value = if ty.is_float() {
+                    token.parse::<f64>().ok()
+                } else {
+                    token.parse::<i64>().ok().map(|v| v as f64)
+                };
+                value.ok_or_else(|| CadStreamError::InvalidNumber {
+                    location: None,
+                    token: token.to_string(),
+                })
+            }
+            BodyReader::Binary {
+                cursor,
+                big_endian,
+                body_offset,
+            } => {
+                let offset = *body_offset + cursor.position() as usize;
+                Self::read_binary(cursor, *big_endian, ty).map_err(|_| {
+                    CadStreamError::truncated("PLY body truncated")
+                        .at(SourceLocation::ByteOffset(offset))
+                })
+            }
+        }
+    }
+
+    fn read_binary(
+        cursor: &mut Cursor<&[u8]>,
+        big_endian: bool,
+        ty: ScalarType,
+    ) -> std::io::Result<f64> {
+        macro_rules! read_as {
+            ($t:ty) => {{
+                let mut buf = [0u8; std::mem::size_of::<$t>()];
+                cursor.read_exact(&mut buf)?;
+                if big_endian {
+                    <$t>::from_be_bytes(buf) as f64
+                } else {
+                    <$t>::from_le_bytes(buf) as f64
+                }
+            }};
+        }
+
+        Ok(match ty {
+            ScalarType::Int8 => read_as!(i8),
+            ScalarType::UInt8 => read_as!(u8),
+            ScalarType::Int16 => read_as!(i16),
+            ScalarType::UInt16 => read_as!(u16),
+            ScalarType::Int32 => read_as!(i32),
+            ScalarType::UInt32 => read_as!(u32),
+            ScalarType::Float32 => read_as!(f32),
+            ScalarType::Float64 => read_as!(f64),
+        })
+    }
+
+    /// Read a full element record, returning scalar values and list contents
+    fn read_record(&mut self, element: &Element) -> Result<(Vec<f64>, Vec<Vec<f64>>)> {
+        let mut scalars = Vec::with_capacity(element.properties.len());
+        let mut lists = Vec::new();
+
+        for property in &element.properties {
+            match property.kind {
+                PropertyKind::Scalar(ty) => scalars.push(self.read(ty)?),
+                PropertyKind::List {
+                    count_type,
+                    item_type,
+                } => {
+                    // Keep scalar positions aligned with property indices
+                    scalars.push(0.0);
+                    let count = self.read(count_type)?;
+                    if count < 0.0 {
+                        return Err(CadStreamError::malformed(format!(
+                            "Negative list length in '{}'",
+                            property.name
+                        )));
+                    }
+                    let items = (0..count as usize)
+                        .map(|_| self.read(item_type))
+                        .collect::<Result<Vec<_>>>()?;
+                    lists.push(items);
+                }
+            }
+        }
+
+        Ok((scalars, lists))
+    }
+}
+
+/// Vertex property positions resolved from the header
+struct VertexLayout {
+    position: [usize; 3],
+    normal: Option<[usize; 3]>,
+    color: Option<([usize; 3], Option<usize>)>,
+    color_is_float: bool,
+}
+
+impl VertexLayout {
+    fn from_element(element: &Element) -> Result<Self> {
+        let index = |names: &[&str]| element.scalar_index(names);
+
+        let position = [index(&["x"]), index(&["y"]), index(&["z"])];
+        let position = match position {
+            [Some(x), Some(y), Some(z)] => [x, y, z],
+            _ => {
+                return Err(CadStreamError::malformed(
+                    "PLY vertex element is missing x/y/z properties",
+                ))
+            }
+        };
+
+        let normal = match [index(&["nx"]), index(&["ny"]), index(&["nz"])] {
+            [Some(x), Some(y), Some(z)] => Some([x, y, z]),
+            _ => None,
+        };
+
+        let color = match [
+            index(&["red", "r", "diffuse_red"]),
+            index(&["green", "g", "diffuse_green"]),
+            index(&["blue", "b", "diffuse_blue"]),
+        ] {
+            [Some(r), Some(g), Some(b)] => Some(([r, g, b], index(&["alpha", "a"]))),
+            _ => None,
+        };
+
+        let color_is_float = color.is_some_and(|([r, _, _], _)| {
+            matches!(element.properties[r].kind, PropertyKind::Scalar(ty) if ty.is_float())
+        });
+
+        Ok(Self {
+            position,
+            normal,
+            color,
+            color_is_float,
+        })
+    }
+
+    fn color_channel(&self, value: f64) -> u8 {
+        if self.color_is_float {
+            (value * 255.0).round().clamp(0.0, 255.0) as u8
+        } else {
+            value.clamp(0.0, 255.0) as u8
+        }
+    }
+}
+
+impl PlyParser {
+    pub fn new() -> Self {
+        Self
+    }
+
+    fn parse_header(&self, data: &[u8]) -> Result<PlyHeader> {
+        const END_HEADER: &[u8] = b"end_header";
+
+        let end = data
+            .windows(END_HEADER.len())
+            .position(|w| w == END_HEADER)
+            .ok_or_else(|| CadStreamError::malformed("Invalid PLY: missing 'end_header'"))?;
+
+        // The body starts right after the newline terminating `end_header`
+        let body_offset = data[end..]
+            .iter()
+            .position(|&b| b == b'\n')
+            .map(|p| end + p + 1)
+            .unwrap_or(data.len());
+
+        let header = String::from_utf8_lossy(&data[..end]);
+        let mut lines = header.lines().map(|l| l.trim()).filter(|l| !l.is_empty());
+
+        if lines.next() != Some("ply") {
+            return Err(CadStreamError::malformed(
+                "Invalid PLY: missing 'ply' magic",
+            ));
+        }
+
+        let mut encoding = None;
+        let mut elements: Vec<Element> = Vec::new();
+
+        for line in lines {
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            match parts.as_slice() {
+                ["format", format, _version] => {
+                    encoding = Some(match *format {
+                        "ascii" => PlyEncoding::Ascii,
+                        "binary_little_endian" => PlyEncoding::BinaryLittleEndian,
+                        "binary_big_endian" => PlyEncoding::BinaryBigEndian,
+                        other => {
+                            return Err(CadStreamError::UnsupportedFormat(format!("PLY {}", other)))
+                        }
+                    });
+                }
+                ["comment", ..] | ["obj_info", ..] => {}
+                ["element", name, count] => {
+                    elements.push(Element {
+                        name: name.to_string(),
+                        count: count.parse().map_err(|_| CadStreamError::InvalidNumber {
+                            location: None,
+                            token: count.to_string(),
+                        })?,
+                        properties: Vec::new(),
+                    });
+                }
+                ["property", "list", count_type, item_type, name] => {
+                    let element = elements.last_mut().ok_or_else(|| {
+                        CadStreamError::malformed(format!(
+                            "PLY property before any element: {}",
+                            line
+                        ))
+                    })?;
+                    element.properties.push(Property {
+                        name: name.to_string(),
+                        kind: PropertyKind::List {
+                            count_type: ScalarType::from_name(count_type)?,
+                            item_type: ScalarType::from_name(item_type)?,
+                        },
+                    });
+                }
+                ["property", ty, name] => {
+                    let element = elements.last_mut().ok_or_else(|| {
+                        CadStreamError::malformed(format!(
+                            "PLY property before any element: {}",
+                            line
+                        ))
+                    })?;
+                    element.properties.push(Property {
+                        name: name.to_string(),
+                        kind: PropertyKind::Scalar(ScalarType::from_name(ty)?),
+                    });
+                }
+                _ => {
+                    return Err(CadStreamError::malformed(format!(
+                        "Invalid PLY header line: {}",
+                        line
+                    )))
+                }
+            }
+        }
+
+        Ok(PlyHeader {
+            encoding: encoding
+                .ok_or_else(|| CadStreamError::malformed("Invalid PLY: missing 'format' line"))?,
+            elements,
+            body_offset,
+        })
+    }
+
+    fn parse_ply(&self, data: &[u8]) -> Result<IndexedMesh> {
+        let header = self.parse_header(data)?;
+        let body = &data[header.body_offset..];
+
+        let ascii_body;
+        let mut reader = match header.encoding {
+            PlyEncoding::Ascii => {
+                ascii_body = String::from_utf8_lossy(body);
+                BodyReader::Ascii {
+                    tokens: ascii_body.split_ascii_whitespace(),
+                    body: &ascii_body,
+                    first_line: data[..header.body_offset]
+                        .iter()
+                        .filter(|&&b| b == b'\n')
+                        .count()
+                        + 1,
+                }
+            }
+            PlyEncoding::BinaryLittleEndian => BodyReader::Binary {
+                cursor: Cursor::new(body),
+                big_endian: false,
+                body_offset: header.body_offset,
+            },
+            PlyEncoding::BinaryBigEndian => BodyReader::Binary {
+                cursor: Cursor::new(body),
+                big_endian: true,
+                body_offset: header.body_offset,
+            },
+        };
+
+        let mut positions: Vec<Vec3> = Vec::new();
+        let mut normals: Vec<Vec3> = Vec::new();
+        let mut colors: Vec<Rgba> = Vec::new();
+        let mut faces: Vec<Vec<usize>> = Vec::new();
+
+        for element in &header.elements {
+            match element.name.as_str() {
+                "vertex" => {
+                    let layout = VertexLayout::from_element(element)?;
+                    positions.reserve(element.capacity_hint(header.encoding, body.len()));
+
+                    for _ in 0..element.count {
+                        let (values, _) = reader.read_record(element)?;
+
+                        let [x, y, z] = layout.position;
+                        positions.push(Vec3::new(
+                            values[x] as f32,
+                            values[y] as f32,
+                            values[z] as f32,
+                        ));
+
+                        if let Some([nx, ny, nz]) = layout.normal {
+                            normals.push(Vec3::new(
+                                values[nx] as f32,
+                                values[ny] as f32,
+                                values[nz] as f32,
+                            ));
+                        }
+
+                        if let Some(([r, g, b], alpha)) = layout.color {
+                            colors.push([
+                                layout.color_channel(values[r]),
+                                layout.color_channel(values[g]),
+                                layout.color_channel(values[b]),
+                                alpha.map_or(255, |a| layout.color_channel(values[a])),
+                            ]);
+                        }
+                    }
+                }
+                "face" => {
+                    let list_index = element
+                        .properties
+                        .iter()
+                        .filter(|p| matches!(p.kind, PropertyKind::List { .. }))
+                        .position(|p| p.name == "vertex_indices" || p.name == "vertex_index")
+                        .ok_or_else(|| {
+                            CadStreamError::malformed("PLY face element has no vertex_indices list")
+                        })?;
+
+                    faces.reserve(element.capacity_hint(header.encoding, body.len()));
+                    for _ in 0..element.count {
+                        let position = reader.position();
+                        let (_, mut lists) = reader.read_record(element)?;
+                        let indices = lists
+                            .swap_remove(list_index)
+                            .into_iter()
+                            .map(|index| {
+                                // Float lists and signed types can hold what no cast fixes
+                                if index.is_finite() && index >= 0.0 && index.fract() == 0.0 {
+                                    return Ok(index as usize);
+                                }
+                                Err(CadStreamError::malformed(format!(
+                                    "Face {} has invalid vertex index {}",
+                                    faces.len(),
+                                    index
+                                ))
+                                .at(reader.location(position)))
+                            })
+                            .collect::<Result<_>>()?;
+                        faces.push(indices);
+                    }
+                }
+                _ => {
+                    // Unknown elements still have to be consumed to reach the next one
+                    for _ in 0..element.count {
+                        reader.read_record(element)?;
+                    }
+                }
+            }
+        }
+
+        let mut mesh = IndexedMesh {
+            colors: (!colors.is_empty()).then_some(colors),
+            ..Default::default()
+        };
+
+        for (face_index, face) in faces.iter().enumerate() {
+            if face.len() < 3 {
+                return Err(CadStreamError::malformed(format!(
+                    "Face {} has fewer than 3 vertices",
+                    face_index
+                )));
+            }
+            if let Some(&bad) = face.iter().find(|&&i| i >= positions.len()) {
+                return Err(CadStreamError::malformed(format!(
+                    "Face {} references vertex {} but only {} vertices are defined",
+                    face_index,
+                    bad,
+                    positions.len()
+                )));
+            }
+
+            // Fan-triangulate around the first corner
+            for i in 1..face.len() - 1 {
+                let corners = [face[0], face[i], face[i + 1]];
+                mesh.normals
+                    .push(self.face_normal(&corners, &positions, &normals));
+                mesh.faces.push(corners.map(|c| c as u32));
+            }
+        }
+
+        mesh.positions = positions;
+        Ok(mesh)
+    }
+
+    fn face_normal(&self, corners: &[usize; 3], positions: &[Vec3], normals: &[Vec3]) -> Vec3 {
+        let vertices = corners.map(|c| positions[c]);
+
+        // Prefer the averaged vertex normals, fall back to the geometric face normal
+        let normal = if normals.is_empty() {
+            Vec3::ZERO
+        } else {
+            corners
+                .iter()
+                .fold(Vec3::ZERO, |acc, &c| acc + normals[c])
+                .normalize_or_zero()
+        };
+
+        if normal != Vec3::ZERO {
+            normal
+        } else {
+            (vertices[1] - vertices[0])
+                .cross(vertices[2] - vertices[0])
+                .normalize_or_zero()
+        }
+    }
+}
+
+impl FileParser for PlyParser {
+    fn parse_data(&self, data: &[u8], name: String) -> Result<CadModel> {
+        let mesh = self.parse_ply(data)?;
+
+        println!(
+            "📐 Parsed {} triangles from {}{}",
+            mesh.faces.len(),
+            name,
+            if mesh.colors.is_some() {
+                " (with vertex colors)"
+            } else {
+                ""
+            }
+        );
+
+        let mut model = CadModel::from_indexed(name, mesh);
+        model.precision_info.file_size_bytes = data.len();
+
+        Ok(model)
+    }
+
+    fn supported_formats(&self) -> &[FileFormat] {
+        &[FileFormat::Ply]
+    }
+
+    fn parser_name(&self) -> &'static str {
+        "PLY Parser"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ascii_ply_with_colors() {
+        let ply_data = b"ply
+format ascii 1.0
+comment unit square
+element vertex 4
+property float x
+property float y
+property float z
+property uchar red
+property uchar green
+property uchar blue
+element face 1
+property list uchar int vertex_indices
+end_header
+0 0 0 255 0 0
+1 0 0 0 255 0
+1 1 0 0 0 255
+0 1 0 255 255 255
+4 0 1 2 3
+";
+
+        let parser = PlyParser::new();
+        let model = parser
+            .parse_data(ply_data, "square.ply".to_string())
+            .unwrap();
+
+        assert_eq!(model.triangles.len(), 2);
+        assert_eq!(model.triangles[1].vertices[2], Vec3::new(0.0, 1.0, 0.0));
+        assert_eq!(model.triangles[0].normal, Vec3::new(0.0, 0.0, 1.0));
+
+        let colors = model.vertex_colors.unwrap();
+        assert_eq!(
+            colors[0],
+            [[255, 0, 0, 255], [0, 255, 0, 255], [0, 0, 255, 255]]
+        );
+        assert_eq!(colors[1][2], [255, 255, 255, 255]);
+    }
+
+    #[test]
+    fn test_invalid_vertex_indices_are_located() {
+        let header = "ply\nformat ascii 1.0\nelement vertex 3\nproperty float x\nproperty float y\nproperty float z\nelement face 2\nproperty list uchar float vertex_indices\nend_header\n0 0 0\n1 0 0\n0 1 0\n3 0 1 2\n";
+        for index in ["-1", "1.5", "nan", "inf"] {
+            let data = format!("{}3 0 {} 2\n", header, index);
+            let error = PlyParser::new()
+                .parse_data(data.as_bytes(), "bad.ply".to_string())
+                .unwrap_err();
+            assert!(
+                matches!(error, CadStreamError::Malformed { .. }),
+                "{}",
+                error
+            );
+            assert_eq!(
+                error.location(),
+                Some(SourceLocation::Line {
+                    line: 14,
+                    column: 1
+                })
+            );
+        }
+
+        let mut data = b"ply\nformat binary_little_endian 1.0\nelement vertex 3\nproperty float x\nproperty float y\nproperty float z\nelement face 1\nproperty list uchar int vertex_indices\nend_header\n".to_vec();
+        let body_offset = data.len();
+        data.extend_from_slice(&[0; 36]);
+        data.push(3);
+        for index in [0i32, -1, 2] {
+            data.extend_from_slice(&index.to_le_bytes());
+        }
+        let error = PlyParser::new()
+            .parse_data(&data, "bad.ply".to_string())
+            .unwrap_err();
+        assert_eq!(
+            error.location(),
+            Some(SourceLocation::ByteOffset(body_offset + 36))
+        );
+    }
+
+    #[test]
+    fn test_huge_element_count_fails_without_allocating() {
+        let data = b"ply\nformat binary_little_endian 1.0\nelement vertex 4000000000000\nproperty float x\nproperty float y\nproperty float z\nend_header\n\0\0\0\0";
+        assert!(matches!(
+            PlyParser::new().parse_data(data, "huge.ply".to_string()),
+            Err(CadStreamError::Truncated { .. })
+        ));
+    }
+
+    #[test]
+    fn test_parse_binary_big_endian_ply() {
+        let mut ply_data = b"ply
+format binary_big_endian 1.0
+element vertex 3
+property float x
+property float y
+property float z
+element face 1
+property list uchar uint vertex_indices
+end_header
+"
+        .to_vec();
+
+        for vertex in [[0.0f32, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, 1.0]] {
+            for component in vertex {
+                ply_data.extend_from_slice(&component.to_be_bytes());
+            }
+        }
+        ply_data.push(3);
+        for index in [0u32, 1, 2] {
+            ply_data.extend_from_slice(&index.to_be_bytes());
+        }
+
+        let parser = PlyParser::new();
+        let model = parser.parse_data(&ply_data, "tri.ply".to_string()).unwrap();
+
+        assert_eq!(model.triangles.len(), 1);
+        assert_eq!(model.triangles[0].vertices[2], Vec3::new(0.0, 0.0, 1.0));
+        assert_eq!(model.triangles[0].normal, Vec3::new(0.0, -1.0, 0.0));
+        assert!(model.vertex_colors.is_none());
+    }
+}
@@ -0,0 +1,266 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+
+use super::{FileFormat, FileParser, ParserFactory};
+use crate::error::{CadStreamError, Result};
+
+/// Priority of the parsers registered by [`ParserRegistry::new`]
+pub const BUILTIN_PRIORITY: i32 = 0;
+
+#[derive(Clone)]
+struct Registration {
+    parser: Arc<dyn FileParser>,
+    priority: i32,
+}
+
+/// Parsers available at runtime, looked up by format
+///
+/// Several parsers can register for the same format. The one with the highest
+/// priority is used, ties going to the one registered first, unless another one is
+/// picked by name with [`ParserRegistry::prefer`]. Downstream crates can register their
+/// own parsers, including for formats known only by a [`FileFormat::Custom`] extension.
+#[derive(Clone)]
+pub struct ParserRegistry {
+    /// Sorted by descending priority
+    parsers: Vec<Registration>,
+    preferred: HashMap<FileFormat, &'static str>,
+}
+
+impl Default for ParserRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for ParserRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ParserRegistry")
+            .field(
+                "parsers",
+                &self
+                    .parsers
+                    .iter()
+                    .map(|r| (r.parser.parser_name(), r.priority))
+                    .collect::<Vec<_>>(),
+            )
+            .field("preferred", &self.preferred)
+            .finish()
+    }
+}
+
+impl ParserRegistry {
+    /// A registry with every parser compiled into this build
+    ///
+    /// With both STL features enabled, `stl_io` is registered first and wins by default.
+    pub fn new() -> Self {
+        let mut registry = Self::empty();
+        let builtin = [
+            ParserFactory::create_stl_io_parser(),
+            ParserFactory::create_custom_stl_parser(),
+            ParserFactory::create_obj_parser(),
+            ParserFactory::create_ply_parser(),
+            ParserFactory::create_gltf_parser(),
+            ParserFactory::create_threemf_parser(),
+        ];
+        for parser in builtin.into_iter().flatten() {
+            registry.register_boxed(parser, BUILTIN_PRIORITY);
+        }
+        registry
+    }
+
+    /// A registry without any parsers
+    pub fn empty() -> Self {
+        Self {
+            parsers: Vec::new(),
+            preferred: HashMap::new(),
+        }
+    }
+
+    /// Add `parser` for each of its [`FileParser::supported_formats`]
+    pub fn register<P: FileParser + 'static>(&mut self, parser: P, priority: i32) -> &mut Self {
+        self.register_boxed(Box::new(parser), priority)
+    }
+
+    pub fn register_boxed(&mut self, parser: Box<dyn FileParser>, priority: i32) -> &mut Self {
+        let index = self
+            .parsers
+            .iter()
+            .position(|r| r.priority < priority)
+            .unwrap_or(self.parsers.len());
+        self.parsers.insert(
+            index,
+            Registration {
+                parser: parser.into(),
+                priority,
+            },
+        );
+        self
+    }
+
+    /// Use the parser called `name` for `format` regardless of priorities
+    pub fn prefer(&mut self, format: FileFormat, name: &str) -> Result<&mut Self> {
+        let parser = self
+            .parsers_for(format)
+            .find(|parser| parser.parser_name().eq_ignore_ascii_case(name))
+            .ok_or_else(|| {
+                CadStreamError::ParserUnavailable(format!(
+                    "No parser named '{}' is registered for {:?} files",
+                    name, format
+                ))
+            })?;
+
+        self.preferred.insert(format, parser.parser_name());
+        Ok(self)
+    }
+
+    /// The registered parser called `name`, for selecting a parser for a single call
+    pub fn parser(&self, name: &str) -> Option<Arc<dyn FileParser>> {
+        self.parsers
+            .iter()
+            .find(|r| r.parser.parser_name().eq_ignore_ascii_case(name))
+            .map(|r| r.parser.clone())
+    }
+
+    /// Every parser registered for `format`, highest priority first
+    pub fn parsers_for(&self, format: FileFormat) -> impl Iterator<Item = &Arc<dyn FileParser>> {
+        self.parsers
+            .iter()
+            .map(|r| &r.parser)
+            .filter(move |parser| parser.supported_formats().contains(&format))
+    }
+
+    /// The preferred parser for `format`, or the one with the highest priority
+    pub fn parser_for(&self, format: FileFormat) -> Result<Arc<dyn FileParser>> {
+        let preferred = self.preferred.get(&format);
+        let mut candidates = self.parsers_for(format);
+        let parser = match preferred {
+            Some(name) => candidates.find(|parser| parser.parser_name() == *name),
+            None => candidates.next(),
+        };
+
+        parser.cloned().ok_or_else(|| {
+            CadStreamError::ParserUnavailable(format!(
+                "No parser is registered for {:?} files",
+                format
+            ))
+        })
+    }
+
+    /// Detect the format of file contents and pick its parser
+    pub fn parser_for_data(&self, data: &[u8], path: &Path) -> Result<Arc<dyn FileParser>> {
+        self.parser_for(self.detect(data, path)?)
+    }
+
+    /// Like [`FileFormat::detect`], also recognising the extensions of custom formats
+    pub fn detect(&self, data: &[u8], path: &Path) -> Result<FileFormat> {
+        match self.format_for_path(path) {
+            // Custom formats have no magic bytes the built-in sniffing knows about
+            Some(format @ FileFormat::Custom(_)) => Ok(format),
+            _ => FileFormat::detect(data, path),
+        }
+    }
+
+    /// The registered format named by any extension of `path`, see [`FileFormat::from_path`]
+    pub fn format_for_path(&self, path: &Path) -> Option<FileFormat> {
+        FileFormat::find_in_path(path, &self.formats())
+    }
+
+    /// Every format with at least one registered parser
+    pub fn formats(&self) -> Vec<FileFormat> {
+        let mut formats = Vec::new();
+        for registration in &self.parsers {
+            for format in registration.parser.supported_formats() {
+                if !formats.contains(format) {
+                    formats.push(*format);
+                }
+            }
+        }
+        formats
+    }
+
+    /// Extensions of every format with at least one registered parser
+    pub fn supported_extensions(&self) -> Vec<&'static str> {
+        self.formats()
+            .into_iter()
+            .flat_map(|format| format.extensions().to_vec())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cad_data::CadModel;
+
+    struct NamedParser {
+        name: &'static str,
+        formats: Vec<FileFormat>,
+    }
+
+    impl FileParser for NamedParser {
+        fn parse_data(&self, _data: &[u8], name: String) -> Result<CadModel> {
+            Ok(CadModel::new(name, Vec::new()))
+        }
+
+        fn supported_formats(&self) -> &[FileFormat] {
+            &self.formats
+        }
+
+        fn parser_name(&self) -> &'static str {
+            self.name
+        }
+    }
+
+    #[test]
+    fn test_priorities_preferences_and_custom_formats() {
+        let mut registry = ParserRegistry::empty();
+        registry
+            .register(
+                NamedParser {
+                    name: "Slow STL",
+                    formats: vec![FileFormat::Stl],
+                },
+                -1,
+            )
+            .register(
+                NamedParser {
+                    name: "Fast STL",
+                    formats: vec![FileFormat::Stl],
+                },
+                5,
+            )
+            .register(
+                NamedParser {
+                    name: "Acme",
+                    formats: vec![FileFormat::Custom("acme")],
+                },
+                BUILTIN_PRIORITY,
+            );
+
+        let stl = Path::new("part.stl");
+        assert_eq!(
+            registry
+                .parser_for_data(b"solid", stl)
+                .unwrap()
+                .parser_name(),
+            "Fast STL"
+        );
+        registry.prefer(FileFormat::Stl, "slow stl").unwrap();
+        assert_eq!(
+            registry.parser_for(FileFormat::Stl).unwrap().parser_name(),
+            "Slow STL"
+        );
+        assert!(registry.prefer(FileFormat::Stl, "Acme").is_err());
+        assert!(registry.parser_for(FileFormat::Obj).is_err());
+
+        // Custom formats are found by extension, even behind a temporary suffix
+        let acme = Path::new("bracket.ACME.tmp");
+        assert_eq!(
+            registry.detect(b"solid", acme).unwrap(),
+            FileFormat::Custom("acme")
+        );
+        assert_eq!(registry.supported_extensions(), ["stl", "acme"]);
+    }
+}
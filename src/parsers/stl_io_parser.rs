@@ -1,56 +1,124 @@
 use super::{FileFormat, FileParser};
-use crate::cad_data::{CadModel, Triangle};
-use anyhow::{Context, Result};
+use crate::cad_data::{CadModel, IndexedMesh, MeshPart};
+use crate::error::{CadStreamError, Result};
+use crate::stl_parser::{StlEncoding, StlParser};
 use glam::Vec3;
-use std::io::Cursor;
+use std::io::{self, Cursor};
+use stl_io::{AsciiStlReader, BinaryStlReader};
 
 /// STL parser implementation using the stl_io crate
 pub struct StlIoParser;
 
+impl Default for StlIoParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl StlIoParser {
     pub fn new() -> Self {
         Self
     }
+
+    /// stl_io stops at the first `endsolid`, so hand it one `solid` block at a time
+    fn parse_ascii_solids(&self, data: &[u8]) -> Result<(IndexedMesh, Vec<MeshPart>)> {
+        let mut mesh = IndexedMesh::default();
+        let mut parts = Vec::new();
+        let mut current_solid: Option<(String, usize)> = None;
+        let mut offset = 0;
+
+        for line in data.split_inclusive(|&b| b == b'\n') {
+            let text = String::from_utf8_lossy(line);
+            let text = text.trim();
+
+            if text.starts_with("endsolid") {
+                let (name, start) = current_solid.take().ok_or_else(|| {
+                    CadStreamError::malformed("Invalid ASCII STL: 'endsolid' without 'solid'")
+                })?;
+                let mut cursor = Cursor::new(&data[start..offset + line.len()]);
+                let stl = AsciiStlReader::create_triangle_iterator(&mut cursor)
+                    .and_then(|mut triangles| triangles.as_indexed_triangles())
+                    .map_err(Self::stl_io_error)?;
+
+                let first_face = mesh.faces.len();
+                self.append_mesh(&mut mesh, &stl);
+                parts.push(MeshPart {
+                    name,
+                    triangles: first_face..mesh.faces.len(),
+                });
+            } else if text.starts_with("solid") && current_solid.is_none() {
+                current_solid = Some((text["solid".len()..].trim().to_string(), offset));
+            }
+
+            offset += line.len();
+        }
+
+        if parts.is_empty() || current_solid.is_some() {
+            return Err(CadStreamError::malformed(
+                "Invalid ASCII STL: missing 'endsolid'",
+            ));
+        }
+
+        Ok((mesh, parts))
+    }
+
+    /// stl_io reports malformed data as `InvalidData` I/O errors
+    fn stl_io_error(error: io::Error) -> CadStreamError {
+        if error.kind() == io::ErrorKind::InvalidData {
+            CadStreamError::malformed(error.to_string())
+        } else {
+            error.into()
+        }
+    }
+
+    /// Append an stl_io mesh, keeping its shared vertices
+    fn append_mesh(&self, mesh: &mut IndexedMesh, stl: &stl_io::IndexedMesh) {
+        let base = mesh.positions.len() as u32;
+        mesh.positions
+            .extend(stl.vertices.iter().map(|v| Vec3::new(v[0], v[1], v[2])));
+        for face in &stl.faces {
+            mesh.faces.push(face.vertices.map(|i| base + i as u32));
+            mesh.normals
+                .push(Vec3::new(face.normal[0], face.normal[1], face.normal[2]));
+        }
+    }
 }
 
 impl FileParser for StlIoParser {
     fn parse_data(&self, data: &[u8], name: String) -> Result<CadModel> {
-        let mut cursor = Cursor::new(data);
-
-        // Use stl_io to parse the STL data
-        let stl = stl_io::read_stl(&mut cursor)
-            .with_context(|| format!("Failed to parse STL data for {}", name))?;
-
-        // Convert stl_io mesh to our Triangle format
-        let triangles: Vec<Triangle> = stl
-            .faces
-            .into_iter()
-            .map(|face| {
-                // Get the three vertices for this face
-                let v0 = stl.vertices[face.vertices[0]];
-                let v1 = stl.vertices[face.vertices[1]];
-                let v2 = stl.vertices[face.vertices[2]];
-
-                let vertices = [
-                    Vec3::new(v0[0], v0[1], v0[2]),
-                    Vec3::new(v1[0], v1[1], v1[2]),
-                    Vec3::new(v2[0], v2[1], v2[2]),
-                ];
-
-                // Use the face normal
-                let normal = Vec3::new(face.normal[0], face.normal[1], face.normal[2]);
-
-                Triangle { vertices, normal }
-            })
-            .collect();
+        // stl_io only probes for a leading "solid", share the custom parser's detection
+        // so binary files with a "solid" header load the same way with both parsers
+        let (mesh, parts, facet_colors) =
+            StlParser::parse_with_fallback(data, |encoding| match encoding {
+                StlEncoding::Ascii => {
+                    let (mesh, parts) = self.parse_ascii_solids(data)?;
+                    Ok((mesh, parts, None))
+                }
+                StlEncoding::Binary => {
+                    let mut cursor = Cursor::new(data);
+                    let stl = BinaryStlReader::create_triangle_iterator(&mut cursor)
+                        .and_then(|mut triangles| triangles.as_indexed_triangles())
+                        .map_err(Self::stl_io_error)?;
+                    let mut mesh = IndexedMesh::default();
+                    self.append_mesh(&mut mesh, &stl);
+                    // stl_io drops the attribute bytes, decode colors from the raw data
+                    Ok((mesh, Vec::new(), StlParser::binary_facet_colors(data)))
+                }
+            })?;
 
         println!(
             "📐 Parsed {} triangles from {} (using stl_io)",
-            triangles.len(),
+            mesh.faces.len(),
             name
         );
 
-        let mut model = CadModel::new(name, triangles);
+        let mut model = CadModel::from_indexed(name, mesh);
+        model.parts = parts;
+        if let Some(colors) = facet_colors {
+            // Facet colors can't live on shared vertices, weld again when rendering
+            model.vertex_colors = Some(colors.into_iter().map(|c| [c; 3]).collect());
+            model.indexed = None;
+        }
         model.precision_info.file_size_bytes = data.len();
 
         Ok(model)
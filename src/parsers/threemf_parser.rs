@@ -0,0 +1,799 @@
+use super::{FileFormat, FileParser, ParseOptions, ParseReport, SourceLocation};
+use crate::cad_data::{CadModel, MeshPart, Rgba, Triangle, DEFAULT_COLOR};
+use crate::error::{CadStreamError, Result};
+use glam::{Mat4, Vec3, Vec4};
+use roxmltree::{Document, Node};
+use std::collections::HashMap;
+use std::io::{Cursor, Read};
+use std::str::FromStr;
+use zip::ZipArchive;
+
+/// Model part used when the package relationships don't name one
+const DEFAULT_MODEL_PATH: &str = "3D/3dmodel.model";
+
+/// 3MF parser for the zip package of XML model parts written by slicers and CAD tools
+///
+/// Every `<build>` item becomes a part, with its object's mesh and `<component>`
+/// references placed by the item and component transforms. Components in other model
+/// parts (the production extension's `p:path`) are resolved within the package.
+/// Triangles are colored from `basematerials` and `colorgroup` properties, and
+/// coordinates are converted to millimetres. Textures and beam lattices are ignored.
+///
+/// The model parts may unpack to at most [`ParseOptions::decompressed_size_limit`]
+/// bytes, and the placed objects may add up to at most as many triangles as a binary
+/// STL of that size, since shared components can multiply a small file into billions.
+pub struct ThreeMfParser;
+
+impl Default for ThreeMfParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Resources declared in one model part
+#[derive(Default)]
+struct Resources {
+    objects: HashMap<u32, Object>,
+    /// Colors of `basematerials` and `colorgroup` entries by resource id
+    colors: HashMap<u32, Vec<Rgba>>,
+}
+
+struct Object {
+    name: Option<String>,
+    mesh: Option<Mesh>,
+    components: Vec<Component>,
+}
+
+#[derive(Default)]
+struct Mesh {
+    vertices: Vec<Vec3>,
+    triangles: Vec<[u32; 3]>,
+    /// Per-corner colors, `None` for triangles without a color property
+    colors: Vec<Option<[Rgba; 3]>>,
+}
+
+/// A reference to an object, from a `<component>` or a build `<item>`
+struct Component {
+    object_id: u32,
+    /// Model part holding the object, `None` for the referencing part
+    path: Option<String>,
+    transform: Mat4,
+}
+
+/// Triangles gathered while placing the build items
+#[derive(Default)]
+struct SceneMesh {
+    triangles: Vec<Triangle>,
+    colors: Vec<[Rgba; 3]>,
+    parts: Vec<MeshPart>,
+    colored: bool,
+    /// Objects placed so far, which bounds the work even for objects without triangles
+    instances: usize,
+    /// Most triangles and instances the build may expand to
+    max_triangles: usize,
+}
+
+impl ThreeMfParser {
+    pub fn new() -> Self {
+        Self
+    }
+
+    fn parse_package(&self, data: &[u8], options: &ParseOptions) -> Result<SceneMesh> {
+        let mut archive = ZipArchive::new(Cursor::new(data))?;
+        let limit = options.decompressed_size_limit();
+        let mut unpacked = 0;
+
+        let root_path = match read_part(&mut archive, "_rels/.rels", limit, &mut unpacked)? {
+            Some(rels) => root_model_path(&rels)?,
+            None => DEFAULT_MODEL_PATH.to_string(),
+        };
+        let root = read_part(&mut archive, &root_path, limit, &mut unpacked)?.ok_or_else(|| {
+            CadStreamError::malformed(format!("3MF package has no model part {}", root_path))
+        })?;
+
+        let document = parse_xml(&root, &root_path)?;
+        let model = document.root_element();
+        let scale = Mat4::from_scale(Vec3::splat(unit_scale(model)?));
+
+        // Parse every model part up front so components can reference any of them
+        let mut parts = HashMap::new();
+        let resources = self
+            .parse_resources(model)
+            .map_err(|error| in_part(&root_path, error))?;
+        parts.insert(part_key(&root_path), resources);
+        let other_paths: Vec<String> = archive
+            .file_names()
+            .filter(|name| name.to_lowercase().ends_with(".model"))
+            .filter(|name| part_key(name) != part_key(&root_path))
+            .map(str::to_string)
+            .collect();
+        for path in other_paths {
+            if let Some(xml) = read_part(&mut archive, &path, limit, &mut unpacked)? {
+                let document = parse_xml(&xml, &path)?;
+                let resources = self
+                    .parse_resources(document.root_element())
+                    .map_err(|error| in_part(&path, error))?;
+                parts.insert(part_key(&path), resources);
+            }
+        }
+
+        let items = child(model, "build")
+            .into_iter()
+            .flat_map(|build| elements(build, "item"))
+            .map(|item| parse_component(item))
+            .collect::<Result<Vec<_>>>()?;
+
+        let max_depth = parts.values().map(|r| r.objects.len()).sum();
+        let mut mesh = SceneMesh {
+            max_triangles: options.triangle_limit(),
+            ..Default::default()
+        };
+        for item in items {
+            let path = part_key(item.path.as_deref().unwrap_or(&root_path));
+            let object = object(&parts, &path, item.object_id)?;
+
+            let start = mesh.triangles.len();
+            self.add_object(
+                &parts,
+                &path,
+                item.object_id,
+                scale * item.transform,
+                &mut mesh,
+                max_depth,
+            )?;
+            let name = object
+                .name
+                .clone()
+                .unwrap_or_else(|| format!("object {}", item.object_id));
+            mesh.parts.push(MeshPart {
+                name,
+                triangles: start..mesh.triangles.len(),
+            });
+        }
+        Ok(mesh)
+    }
+
+    fn parse_resources(&self, model: Node) -> Result<Resources> {
+        let mut resources = Resources::default();
+        let Some(resources_node) = child(model, "resources") else {
+            return Ok(resources);
+        };
+
+        // Property groups may be declared after the objects that use them
+        for node in resources_node.children().filter(Node::is_element) {
+            let colors = match node.tag_name().name() {
+                "basematerials" => elements(node, "base")
+                    .map(|base| parse_color(base, "displaycolor"))
+                    .collect::<Result<Vec<_>>>()?,
+                "colorgroup" => elements(node, "color")
+                    .map(|color| parse_color(color, "color"))
+                    .collect::<Result<Vec<_>>>()?,
+                _ => continue,
+            };
+            resources.colors.insert(required(node, "id")?, colors);
+        }
+
+        for node in elements(resources_node, "object") {
+            let id = required(node, "id")?;
+            let mesh = match child(node, "mesh") {
+                Some(mesh) => Some(self.parse_mesh(node, mesh, &resources.colors)?),
+                None => None,
+            };
+            let components = child(node, "components")
+                .into_iter()
+                .flat_map(|components| elements(components, "component"))
+                .map(|component| parse_component(component))
+                .collect::<Result<Vec<_>>>()?;
+
+            resources.objects.insert(
+                id,
+                Object {
+                    name: node.attribute("name").map(str::to_string),
+                    mesh,
+                    components,
+                },
+            );
+        }
+        Ok(resources)
+    }
+
+    fn parse_mesh(
+        &self,
+        object: Node,
+        mesh: Node,
+        colors: &HashMap<u32, Vec<Rgba>>,
+    ) -> Result<Mesh> {
+        let default_pid = optional::<u32>(object, "pid")?;
+        let default_pindex = optional::<usize>(object, "pindex")?.unwrap_or(0);
+
+        let vertices = child(mesh, "vertices")
+            .into_iter()
+            .flat_map(|vertices| elements(vertices, "vertex"))
+            .map(|vertex| {
+                Ok(Vec3::new(
+                    required(vertex, "x")?,
+                    required(vertex, "y")?,
+                    required(vertex, "z")?,
+                ))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut result = Mesh {
+            vertices,
+            ..Default::default()
+        };
+        for triangle in child(mesh, "triangles")
+            .into_iter()
+            .flat_map(|triangles| elements(triangles, "triangle"))
+        {
+            let indices = [
+                required(triangle, "v1")?,
+                required(triangle, "v2")?,
+                required(triangle, "v3")?,
+            ];
+            if let Some(&index) = indices
+                .iter()
+                .find(|&&index| index as usize >= result.vertices.len())
+            {
+                return Err(CadStreamError::malformed(format!(
+                    "Vertex index {} is out of range for {} vertices",
+                    index,
+                    result.vertices.len()
+                ))
+                .at(location(triangle)));
+            }
+
+            // Triangle properties override the object's, p2/p3 default to p1
+            let pid = optional::<u32>(triangle, "pid")?.or(default_pid);
+            let p1 = optional::<usize>(triangle, "p1")?.unwrap_or(default_pindex);
+            let p2 = optional::<usize>(triangle, "p2")?.unwrap_or(p1);
+            let p3 = optional::<usize>(triangle, "p3")?.unwrap_or(p1);
+            let color = match pid.and_then(|pid| colors.get(&pid)) {
+                Some(group) => {
+                    let corner = |index: usize| {
+                        group.get(index).copied().ok_or_else(|| {
+                            CadStreamError::malformed(format!(
+                                "Property index {} is out of range for {} entries",
+                                index,
+                                group.len()
+                            ))
+                            .at(location(triangle))
+                        })
+                    };
+                    Some([corner(p1)?, corner(p2)?, corner(p3)?])
+                }
+                // Textures and other property kinds aren't read
+                None => None,
+            };
+
+            result.triangles.push(indices);
+            result.colors.push(color);
+        }
+        Ok(result)
+    }
+
+    /// Append the triangles of an object and its components, placed by `transform`
+    fn add_object(
+        &self,
+        parts: &HashMap<String, Resources>,
+        path: &str,
+        object_id: u32,
+        transform: Mat4,
+        mesh: &mut SceneMesh,
+        max_depth: usize,
+    ) -> Result<()> {
+        // Components form a tree, nesting deeper than the object count means a cycle
+        let Some(max_depth) = max_depth.checked_sub(1) else {
+            return Err(CadStreamError::malformed(
+                "Object components contain a cycle",
+            ));
+        };
+        let object = object(parts, path, object_id)?;
+
+        // Sharing components is legal, but lets a small file expand exponentially
+        mesh.instances += 1;
+        let triangle_count = object.mesh.as_ref().map_or(0, |m| m.triangles.len());
+        if mesh.instances > mesh.max_triangles
+            || mesh.triangles.len() + triangle_count > mesh.max_triangles
+        {
+            return Err(CadStreamError::malformed(format!(
+                "Build items expand to more than {} triangles or object instances",
+                mesh.max_triangles
+            )));
+        }
+
+        if let Some(object_mesh) = &object.mesh {
+            // Mirroring transforms turn the winding inside out
+            let mirrored = transform.determinant() < 0.0;
+
+            for (indices, color) in object_mesh.triangles.iter().zip(&object_mesh.colors) {
+                let mut indices = *indices;
+                let mut color = color.unwrap_or([DEFAULT_COLOR; 3]);
+                if mirrored {
+                    indices.swap(1, 2);
+                    color.swap(1, 2);
+                }
+
+                let vertices = indices
+                    .map(|index| transform.transform_point3(object_mesh.vertices[index as usize]));
+                let normal = (vertices[1] - vertices[0])
+                    .cross(vertices[2] - vertices[0])
+                    .normalize_or_zero();
+                mesh.triangles.push(Triangle { vertices, normal });
+                mesh.colors.push(color);
+            }
+            mesh.colored |= object_mesh.colors.iter().any(Option::is_some);
+        }
+
+        for component in &object.components {
+            let component_path = component.path.as_deref().map_or(path.to_string(), part_key);
+            self.add_object(
+                parts,
+                &component_path,
+                component.object_id,
+                transform * component.transform,
+                mesh,
+                max_depth,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Read a part of the package, matching its name case-insensitively as OPC does
+///
+/// `unpacked` counts the bytes read from every part so far, which may not exceed `limit`.
+fn read_part(
+    archive: &mut ZipArchive<Cursor<&[u8]>>,
+    path: &str,
+    limit: u64,
+    unpacked: &mut u64,
+) -> Result<Option<String>> {
+    let key = part_key(path);
+    let Some(name) = archive
+        .file_names()
+        .find(|name| part_key(name) == key)
+        .map(str::to_string)
+    else {
+        return Ok(None);
+    };
+
+    let remaining = limit - *unpacked;
+    let too_large = || {
+        CadStreamError::malformed(format!(
+            "3MF part {} unpacks to more than the {} bytes left of the {} byte limit",
+            name, remaining, limit
+        ))
+    };
+    let part = archive.by_name(&name)?;
+    // The declared size is checked first, but only the bytes read can be trusted
+    if part.size() > remaining {
+        return Err(too_large());
+    }
+
+    let mut bytes = Vec::new();
+    part.take(remaining.saturating_add(1))
+        .read_to_end(&mut bytes)
+        .map_err(|error| {
+            CadStreamError::malformed(format!("Cannot read 3MF part {}: {}", name, error))
+        })?;
+    if bytes.len() as u64 > remaining {
+        return Err(too_large());
+    }
+    *unpacked += bytes.len() as u64;
+
+    String::from_utf8(bytes).map(Some).map_err(|error| {
+        CadStreamError::malformed(format!("Cannot read 3MF part {}: {}", name, error))
+    })
+}
+
+/// Part names as stored in the zip, without the leading `/` used in references
+fn part_key(path: &str) -> String {
+    path.trim_start_matches('/').to_lowercase()
+}
+
+/// The model part named by the package's start relationship
+fn root_model_path(rels: &str) -> Result<String> {
+    let document = parse_xml(rels, "_rels/.rels")?;
+    let target = document
+        .root_element()
+        .children()
+        .filter(|node| node.tag_name().name() == "Relationship")
+        .find(|node| {
+            node.attribute("Type")
+                .is_some_and(|t| t.ends_with("/3dmodel"))
+        })
+        .and_then(|node| node.attribute("Target"));
+    Ok(target
+        .map_or(DEFAULT_MODEL_PATH, |target| target.trim_start_matches('/'))
+        .to_string())
+}
+
+fn parse_xml<'a>(text: &'a str, path: &str) -> Result<Document<'a>> {
+    Document::parse(text)
+        .map_err(|error| CadStreamError::malformed(format!("Invalid XML in {}: {}", path, error)))
+}
+
+fn object<'a>(parts: &'a HashMap<String, Resources>, path: &str, id: u32) -> Result<&'a Object> {
+    parts
+        .get(path)
+        .and_then(|resources| resources.objects.get(&id))
+        .ok_or_else(|| {
+            CadStreamError::malformed(format!("Object {} is not defined in {}", id, path))
+        })
+}
+
+fn in_part(path: &str, error: CadStreamError) -> CadStreamError {
+    match error {
+        CadStreamError::Malformed { location, message } => CadStreamError::Malformed {
+            location,
+            message: format!("{} in {}", message, path),
+        },
+        error => error,
+    }
+}
+
+/// Millimetres per unit of the model's `unit` attribute
+fn unit_scale(model: Node) -> Result<f32> {
+    match model.attribute("unit").unwrap_or("millimeter") {
+        "micron" => Ok(0.001),
+        "millimeter" => Ok(1.0),
+        "centimeter" => Ok(10.0),
+        "inch" => Ok(25.4),
+        "foot" => Ok(304.8),
+        "meter" => Ok(1000.0),
+        unit => {
+            Err(CadStreamError::malformed(format!("Unknown unit '{}'", unit)).at(location(model)))
+        }
+    }
+}
+
+fn parse_component(node: Node) -> Result<Component> {
+    let transform = match node.attribute("transform") {
+        Some(transform) => parse_transform(node, transform)?,
+        None => Mat4::IDENTITY,
+    };
+
+    Ok(Component {
+        object_id: required(node, "objectid")?,
+        // The production extension's p:path, matched by local name
+        path: node
+            .attributes()
+            .find(|attribute| attribute.name() == "path")
+            .map(|attribute| attribute.value().to_string()),
+        transform,
+    })
+}
+
+/// 3MF stores the 4x3 matrix row by row for row vectors, i.e. column by column for
+/// glam's column vectors
+fn parse_transform(node: Node, text: &str) -> Result<Mat4> {
+    let values = text
+        .split_whitespace()
+        .map(|token| number(node, token))
+        .collect::<Result<Vec<f32>>>()?;
+    let [m00, m01, m02, m10, m11, m12, m20, m21, m22, m30, m31, m32] = values[..] else {
+        return Err(CadStreamError::malformed(format!(
+            "Transform needs 12 values, found {}",
+            values.len()
+        ))
+        .at(location(node)));
+    };
+
+    Ok(Mat4::from_cols(
+        Vec4::new(m00, m01, m02, 0.0),
+        Vec4::new(m10, m11, m12, 0.0),
+        Vec4::new(m20, m21, m22, 0.0),
+        Vec4::new(m30, m31, m32, 1.0),
+    ))
+}
+
+/// `#RRGGBB` or `#RRGGBBAA` in sRGB
+fn parse_color(node: Node, attribute: &str) -> Result<Rgba> {
+    let text: String = required(node, attribute)?;
+    let hex = text.strip_prefix('#').filter(|hex| {
+        (hex.len() == 6 || hex.len() == 8) && hex.bytes().all(|b| b.is_ascii_hexdigit())
+    });
+    let Some(hex) = hex else {
+        return Err(
+            CadStreamError::malformed(format!("Invalid color '{}'", text)).at(location(node)),
+        );
+    };
+
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).unwrap_or(255);
+    Ok([
+        channel(0),
+        channel(2),
+        channel(4),
+        if hex.len() == 8 { channel(6) } else { 255 },
+    ])
+}
+
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+    node.children()
+        .find(|child| child.tag_name().name() == name)
+}
+
+fn elements<'a, 'input>(
+    node: Node<'a, 'input>,
+    name: &'static str,
+) -> impl Iterator<Item = Node<'a, 'input>> {
+    node.children()
+        .filter(move |child| child.tag_name().name() == name)
+}
+
+fn required<T: FromStr>(node: Node, attribute: &str) -> Result<T> {
+    optional(node, attribute)?.ok_or_else(|| {
+        CadStreamError::malformed(format!(
+            "<{}> is missing the {} attribute",
+            node.tag_name().name(),
+            attribute
+        ))
+        .at(location(node))
+    })
+}
+
+fn optional<T: FromStr>(node: Node, attribute: &str) -> Result<Option<T>> {
+    node.attribute(attribute)
+        .map(|token| number(node, token))
+        .transpose()
+}
+
+fn number<T: FromStr>(node: Node, token: &str) -> Result<T> {
+    token
+        .trim()
+        .parse()
+        .map_err(|_| CadStreamError::InvalidNumber {
+            location: Some(location(node)),
+            token: token.to_string(),
+        })
+}
+
+fn location(node: Node) -> SourceLocation {
+    let position = node.document().text_pos_at(node.range().start);
+    SourceLocation::Line {
+        line: position.row as usize,
+        column: position.col as usize,
+    }
+}
+
+impl FileParser for ThreeMfParser {
+    fn parse_data(&self, data: &[u8], name: String) -> Result<CadModel> {
+        Ok(self
+            .parse_data_with_options(data, name, &ParseOptions::default())?
+            .0)
+    }
+
+    /// Strict and lenient mode are the same, but the options set the size limit
+    fn parse_data_with_options(
+        &self,
+        data: &[u8],
+        name: String,
+        options: &ParseOptions,
+    ) -> Result<(CadModel, ParseReport)> {
+        let mesh = self.parse_package(data, options)?;
+
+        println!(
+            "📐 Parsed {} triangles in {} build items from {}",
+            mesh.triangles.len(),
+            mesh.parts.len(),
+            name
+        );
+
+        let mut model = CadModel::new(name, mesh.triangles);
+        model.parts = mesh.parts;
+        model.vertex_colors = mesh.colored.then_some(mesh.colors);
+        model.precision_info.file_size_bytes = data.len();
+
+        Ok((model, ParseReport::default()))
+    }
+
+    fn supported_formats(&self) -> &[FileFormat] {
+        &[FileFormat::ThreeMf]
+    }
+
+    fn parser_name(&self) -> &'static str {
+        "3MF Parser"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsers::{load_data, ParserRegistry};
+    use std::io::Write;
+    use std::path::Path;
+    use zip::write::SimpleFileOptions;
+
+    const RELS: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">
+  <Relationship Target="/3D/assembly.model" Id="rel0" Type="http://schemas.microsoft.com/3dmanufacturing/2013/01/3dmodel"/>
+</Relationships>"#;
+
+    /// A red triangle, a bracket made of it twice (once mirrored), and a blue-green
+    /// triangle in a separate model part, in centimetres
+    const ASSEMBLY: &str = r##"<?xml version="1.0" encoding="UTF-8"?>
+<model unit="centimeter" xmlns="http://schemas.microsoft.com/3dmanufacturing/core/2015/02"
+       xmlns:m="http://schemas.microsoft.com/3dmanufacturing/material/2015/02"
+       xmlns:p="http://schemas.microsoft.com/3dmanufacturing/production/2015/06">
+  <resources>
+    <basematerials id="1">
+      <base name="PLA" displaycolor="#FF0000"/>
+    </basematerials>
+    <object id="2" type="model" pid="1" pindex="0">
+      <mesh>
+        <vertices>
+          <vertex x="0" y="0" z="0"/>
+          <vertex x="1" y="0" z="0"/>
+          <vertex x="0" y="1" z="0"/>
+        </vertices>
+        <triangles>
+          <triangle v1="0" v2="1" v3="2"/>
+        </triangles>
+      </mesh>
+    </object>
+    <object id="3" name="bracket">
+      <components>
+        <component objectid="2"/>
+        <component objectid="2" transform="-1 0 0 0 1 0 0 0 1 5 0 0"/>
+      </components>
+    </object>
+    <object id="4" name="insert">
+      <components>
+        <component objectid="1" p:path="/3D/Objects/insert.model"/>
+      </components>
+    </object>
+  </resources>
+  <build>
+    <item objectid="3" transform="1 0 0 0 1 0 0 0 1 0 0 2"/>
+    <item objectid="4"/>
+  </build>
+</model>"##;
+
+    const INSERT: &str = r##"<?xml version="1.0" encoding="UTF-8"?>
+<model unit="millimeter" xmlns="http://schemas.microsoft.com/3dmanufacturing/core/2015/02"
+       xmlns:m="http://schemas.microsoft.com/3dmanufacturing/material/2015/02">
+  <resources>
+    <m:colorgroup id="5">
+      <m:color color="#0000FF80"/>
+      <m:color color="#00FF00"/>
+    </m:colorgroup>
+    <object id="1">
+      <mesh>
+        <vertices>
+          <vertex x="0" y="0" z="0"/>
+          <vertex x="0" y="2" z="0"/>
+          <vertex x="0" y="0" z="2"/>
+        </vertices>
+        <triangles>
+          <triangle v1="0" v2="1" v3="2" pid="5" p1="0" p2="1"/>
+        </triangles>
+      </mesh>
+    </object>
+  </resources>
+</model>"##;
+
+    fn package(parts: &[(&str, &str)]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, data) in parts {
+            writer
+                .start_file(*name, SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(data.as_bytes()).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn test_build_items_components_and_colors() {
+        let data = package(&[
+            ("_rels/.rels", RELS),
+            ("3D/assembly.model", ASSEMBLY),
+            ("3D/Objects/insert.model", INSERT),
+        ]);
+
+        // Detected from the extension even though the model part has a custom name
+        let path = Path::new("plate.3mf");
+        assert_eq!(
+            FileFormat::detect(&data, path).unwrap(),
+            FileFormat::ThreeMf
+        );
+        let loaded = load_data(
+            &data,
+            "plate.3mf".to_string(),
+            path,
+            &ParserRegistry::new(),
+            &ParseOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(loaded.len(), 1);
+        let model = &loaded[0].model;
+
+        let names: Vec<_> = model.parts.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, ["bracket", "insert"]);
+        assert_eq!(model.parts[0].triangles, 0..2);
+        assert_eq!(model.parts[1].triangles, 2..3);
+
+        // Item translation and unit scale, the mirrored copy keeps facing up
+        let triangles = &model.triangles;
+        assert_eq!(
+            triangles[0].vertices,
+            [
+                Vec3::new(0.0, 0.0, 20.0),
+                Vec3::new(10.0, 0.0, 20.0),
+                Vec3::new(0.0, 10.0, 20.0)
+            ]
+        );
+        assert_eq!(triangles[1].vertices[0], Vec3::new(50.0, 0.0, 20.0));
+        assert_eq!(triangles[1].normal, Vec3::Z);
+        assert_eq!(triangles[2].vertices[1], Vec3::new(0.0, 20.0, 0.0));
+
+        let colors = model.vertex_colors.as_ref().unwrap();
+        assert_eq!(colors[1], [[255, 0, 0, 255]; 3]);
+        assert_eq!(
+            colors[2],
+            [[0, 0, 255, 128], [0, 255, 0, 255], [0, 0, 255, 128]]
+        );
+    }
+
+    #[test]
+    fn test_errors_report_part_and_location() {
+        let broken = ASSEMBLY.replace(r#"v3="2"/>"#, r#"v3="7"/>"#);
+        let data = package(&[("_rels/.rels", RELS), ("3D/assembly.model", &broken)]);
+        let error = ThreeMfParser::new()
+            .parse_data(&data, "plate.3mf".to_string())
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "line 17, column 11: Vertex index 7 is out of range for 3 vertices in 3D/assembly.model"
+        );
+
+        // The insert part is missing
+        let data = package(&[("_rels/.rels", RELS), ("3D/assembly.model", ASSEMBLY)]);
+        assert!(ThreeMfParser::new()
+            .parse_data(&data, "plate.3mf".to_string())
+            .is_err());
+    }
+
+    #[test]
+    fn test_expansion_and_part_sizes_are_limited() {
+        // Every object places the previous one twice, 2^30 triangles in all
+        let mut objects = String::from(
+            r#"<object id="0"><mesh><vertices><vertex x="0" y="0" z="0"/><vertex x="1" y="0" z="0"/><vertex x="0" y="1" z="0"/></vertices><triangles><triangle v1="0" v2="1" v3="2"/></triangles></mesh></object>"#,
+        );
+        for id in 1..=30 {
+            objects.push_str(&format!(
+                r#"<object id="{}"><components><component objectid="{}"/><component objectid="{}"/></components></object>"#,
+                id,
+                id - 1,
+                id - 1
+            ));
+        }
+        let model = format!(
+            r#"<model xmlns="http://schemas.microsoft.com/3dmanufacturing/core/2015/02"><resources>{}</resources><build><item objectid="30"/></build></model>"#,
+            objects
+        );
+        let data = package(&[("3D/3dmodel.model", &model)]);
+
+        let options = ParseOptions {
+            max_decompressed_size: Some(1 << 20),
+            ..Default::default()
+        };
+        let parser = ThreeMfParser::new();
+        let error = parser
+            .parse_data_with_options(&data, "bomb.3mf".to_string(), &options)
+            .unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("Build items expand to more than"));
+
+        let options = ParseOptions {
+            max_decompressed_size: Some(model.len() as u64 - 1),
+            ..Default::default()
+        };
+        let error = parser
+            .parse_data_with_options(&data, "bomb.3mf".to_string(), &options)
+            .unwrap_err();
+        assert!(error.to_string().contains("unpacks to more than"));
+    }
+}
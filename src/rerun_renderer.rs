@@ -1,22 +1,43 @@
 use anyhow::Result;
 use rerun as rr;
-use std::sync::Arc;
-use std::time::Duration;
-use tokio::sync::RwLock;
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use std::path::{Component, Path, PathBuf};
+use tokio::sync::broadcast::{self, error::RecvError};
 
-use crate::cad_data::CadModel;
+use crate::cad_data::{CadModel, MeshPart, DEFAULT_COLOR};
+use crate::model_events::{ModelEvent, ModelRegistry};
 
 pub struct RerunRenderer {
     rec: rr::RecordingStream,
-    current_model: Arc<RwLock<Option<CadModel>>>,
+    models: ModelRegistry,
+    events: broadcast::Receiver<ModelEvent>,
+    logged_hashes: HashMap<PathBuf, u64>,
+    /// Model paths are shown relative to this directory, see [`RerunRenderer::set_roots`]
+    base_dir: Option<PathBuf>,
+    frame_count: i64,
+    port: u16,
 }
 
 impl RerunRenderer {
-    pub fn new(current_model: Arc<RwLock<Option<CadModel>>>) -> Result<Self> {
+    pub fn new(models: ModelRegistry, events: broadcast::Receiver<ModelEvent>) -> Result<Self> {
+        Self::with_port(models, events, rr::DEFAULT_SERVER_PORT)
+    }
+
+    /// Create a renderer whose gRPC server listens on `port` instead of the Rerun default
+    pub fn with_port(
+        models: ModelRegistry,
+        events: broadcast::Receiver<ModelEvent>,
+        port: u16,
+    ) -> Result<Self> {
         // Create RecordingStream that serves directly over gRPC
         let rec = rr::RecordingStreamBuilder::new("cadstream")
             .default_enabled(true)
-            .serve_grpc()?;
+            .serve_grpc_opts(
+                "0.0.0.0",
+                port,
+                rr::MemoryLimit::from_fraction_of_total(0.75),
+            )?;
 
         // Log application info
         rec.log_static(
@@ -24,99 +45,259 @@ impl RerunRenderer {
             &rr::TextLog::new("🚀 CAD Stream Processor with Rerun"),
         )?;
 
-        Ok(Self { rec, current_model })
+        Ok(Self {
+            rec,
+            models,
+            events,
+            logged_hashes: HashMap::new(),
+            base_dir: None,
+            frame_count: 0,
+            port,
+        })
     }
 
-    pub async fn run(&self) -> Result<()> {
+    /// Show models relative to the common ancestor of `roots`, usually the watched
+    /// directories, rather than by their full path
+    pub fn set_roots(&mut self, roots: &[PathBuf]) {
+        self.base_dir = roots.iter().cloned().reduce(|ancestor, root| {
+            ancestor
+                .components()
+                .zip(root.components())
+                .take_while(|(a, b)| a == b)
+                .map(|(a, _)| a)
+                .collect()
+        });
+    }
+
+    pub async fn run(&mut self) -> Result<()> {
         println!("🔄 CAD Stream with Rerun visualization started!");
         println!("📁 Add STL files to the directory to see them in Rerun viewer");
-        println!("🌐 Open http://localhost:9090/ in your browser and connect to 0.0.0.0:9876");
+        println!(
+            "🌐 Open http://localhost:9090/ in your browser and connect to 0.0.0.0:{}",
+            self.port
+        );
 
         // Set up initial timeline context
         self.rec.set_time_sequence("frame", 0);
 
-        let mut last_triangle_count = 0;
-        let mut frame_count = 0;
-
         // Log initial state immediately
-        {
-            let model = self.current_model.read().await;
-            if let Some(model) = model.as_ref() {
-                self.log_model(model).await?;
-                last_triangle_count = model.triangles.len();
-                println!(
-                    "📊 Initial data logged to Rerun: {} triangles",
-                    last_triangle_count
-                );
-            } else {
-                // Log empty scene to show Rerun is connected
-                self.rec.log(
-                    "status",
-                    &rr::TextLog::new("🎯 CAD Stream connected! Waiting for STL files..."),
-                )?;
-            }
+        let model_count = self.sync_all().await?;
+        if model_count > 0 {
+            println!("📊 Initial data logged to Rerun: {} models", model_count);
+        } else {
+            // Log empty scene to show Rerun is connected
+            self.rec.log(
+                "status",
+                &rr::TextLog::new("🎯 CAD Stream connected! Waiting for STL files..."),
+            )?;
         }
 
+        // Wait for the file watcher to publish updates
         loop {
-            let model = self.current_model.read().await;
-
-            if let Some(model) = model.as_ref() {
-                // Only update if model changed
-                if model.triangles.len() != last_triangle_count {
-                    frame_count += 1;
-                    self.rec.set_time_sequence("frame", frame_count);
-                    self.log_model(model).await?;
-                    last_triangle_count = model.triangles.len();
+            match self.events.recv().await {
+                Ok(ModelEvent::Updated { path, model }) => {
+                    self.update_model(&path, &model)?;
                 }
+                Ok(ModelEvent::Removed { path }) => {
+                    self.remove_model(&path)?;
+                }
+                Ok(ModelEvent::Renamed { from, to, model }) => {
+                    self.remove_model(&from)?;
+                    self.update_model(&to, &model)?;
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    // Missed some updates, the registry holds the latest models
+                    log::warn!("Renderer lagged behind by {} model events", skipped);
+                    self.sync_all().await?;
+                }
+                Err(RecvError::Closed) => return Ok(()),
             }
-
-            // Check every 100ms for new models
-            tokio::time::sleep(Duration::from_millis(100)).await;
         }
     }
 
-    async fn log_model(&self, model: &CadModel) -> Result<()> {
-        // Convert CAD triangles to Rerun's mesh format
-        let vertices: Vec<[f32; 3]> = model
-            .triangles
+    /// Log every model in the registry that changed since it was last logged and clear
+    /// the ones that are gone
+    async fn sync_all(&mut self) -> Result<usize> {
+        let models: Vec<_> = self
+            .models
+            .read()
+            .await
             .iter()
-            .flat_map(|tri| tri.vertices.iter().map(|v| [v.x, v.y, v.z]))
+            .map(|(path, model)| (path.clone(), model.clone()))
             .collect();
 
-        // Create triangle indices (every 3 vertices forms a triangle)
-        let triangle_indices: Vec<[u32; 3]> = (0..vertices.len())
-            .step_by(3)
-            .map(|i| [i as u32, (i + 1) as u32, (i + 2) as u32])
+        let stale: Vec<PathBuf> = self
+            .logged_hashes
+            .keys()
+            .filter(|path| !models.iter().any(|(p, _)| p == *path))
+            .cloned()
             .collect();
+        for path in &stale {
+            self.remove_model(path)?;
+        }
 
-        // Log the mesh to Rerun
-        self.rec.log(
-            "cad_model/geometry",
-            &rr::Mesh3D::new(vertices)
-                .with_triangle_indices(triangle_indices)
-                .with_albedo_factor([178u8, 178u8, 230u8, 255u8]),
-        )?;
+        for (path, model) in &models {
+            self.update_model(path, model)?;
+        }
+
+        Ok(models.len())
+    }
+
+    fn update_model(&mut self, path: &Path, model: &CadModel) -> Result<()> {
+        // Only update if the model content changed
+        if self.logged_hashes.get(path) == Some(&model.content_hash) {
+            return Ok(());
+        }
+
+        self.frame_count += 1;
+        self.rec.set_time_sequence("frame", self.frame_count);
+
+        // The set of parts may change between versions, drop the previous geometry first
+        if self.logged_hashes.contains_key(path) {
+            self.rec
+                .log(self.model_entity(path, "geometry"), &rr::Clear::recursive())?;
+        }
+
+        self.log_model(path, model)?;
+        self.logged_hashes
+            .insert(path.to_path_buf(), model.content_hash);
+
+        Ok(())
+    }
+
+    /// Clear everything logged for the model loaded from `path`
+    fn remove_model(&mut self, path: &Path) -> Result<()> {
+        if self.logged_hashes.remove(path).is_none() {
+            return Ok(());
+        }
+
+        self.frame_count += 1;
+        self.rec.set_time_sequence("frame", self.frame_count);
+        self.rec
+            .log(self.model_root(path), &rr::Clear::recursive())?;
+
+        println!("🧹 Cleared {} from Rerun", path.display());
+
+        Ok(())
+    }
+
+    /// Root entity of a model, `cad_model/<path relative to its root>`
+    fn model_root(&self, path: &Path) -> rr::EntityPath {
+        Self::entity_root(self.base_dir.as_deref(), path)
+    }
+
+    /// Entity path for a model's child, `cad_model/<path>/<child>`
+    fn model_entity(&self, path: &Path, child: &str) -> rr::EntityPath {
+        self.model_root(path).join(&rr::entity_path!(child))
+    }
+
+    /// Entity path for one part of a model, `cad_model/<path>/geometry/<part>`
+    fn part_entity(&self, path: &Path, part: &str) -> rr::EntityPath {
+        self.model_root(path)
+            .join(&rr::entity_path!("geometry", part))
+    }
+
+    /// Every component of the path below `base_dir`, with the extension and any
+    /// archive entry, so `a/part.stl`, `b/part.stl`, `part.obj` and
+    /// `bundle.zip/part.stl` each get their own entity
+    fn entity_root(base_dir: Option<&Path>, path: &Path) -> rr::EntityPath {
+        let relative = base_dir
+            .and_then(|base_dir| path.strip_prefix(base_dir).ok())
+            .unwrap_or(path);
+
+        let mut parts = vec![rr::EntityPathPart::from("cad_model")];
+        parts.extend(
+            relative
+                .components()
+                .filter_map(|component| match component {
+                    Component::Normal(name) => Some(rr::EntityPathPart::from(
+                        name.to_string_lossy().into_owned(),
+                    )),
+                    _ => None,
+                }),
+        );
+        rr::EntityPath::new(parts)
+    }
+
+    /// Entity names for parts, unnamed parts get their index and duplicates a suffix
+    fn part_entity_names(parts: &[MeshPart]) -> Vec<String> {
+        let mut seen = HashSet::new();
+        parts
+            .iter()
+            .enumerate()
+            .map(|(index, part)| {
+                let base = if part.name.is_empty() {
+                    format!("part_{}", index)
+                } else {
+                    part.name.clone()
+                };
+                let mut name = base.clone();
+                let mut suffix = 2;
+                while !seen.insert(name.clone()) {
+                    name = format!("{}_{}", base, suffix);
+                    suffix += 1;
+                }
+                name
+            })
+            .collect()
+    }
+
+    fn mesh3d(model: &CadModel, triangles: Range<usize>) -> rr::Mesh3D {
+        // Share vertices so each one is only sent once
+        let mesh = model.indexed_range(triangles);
+        let vertices: Vec<[f32; 3]> = mesh.positions.iter().map(|v| v.to_array()).collect();
+
+        let mesh3d = rr::Mesh3D::new(vertices).with_triangle_indices(mesh.faces);
+
+        // The albedo factor multiplies vertex colors, only use it for uncolored models
+        match mesh.colors {
+            Some(colors) => mesh3d.with_vertex_colors(colors),
+            None => mesh3d.with_albedo_factor(DEFAULT_COLOR),
+        }
+    }
+
+    fn log_model(&self, path: &Path, model: &CadModel) -> Result<()> {
+        // Multi-part models (OBJ groups, STL solids) get one entity per part, and
+        // triangles outside every part one of their own
+        let parts = model.covering_parts();
+        if parts.len() > 1 {
+            let names = Self::part_entity_names(&parts);
+            for (part, name) in parts.iter().zip(names) {
+                self.rec.log(
+                    self.part_entity(path, &name),
+                    &Self::mesh3d(model, part.triangles.clone()),
+                )?;
+            }
+        } else {
+            self.rec.log(
+                self.model_entity(path, "geometry"),
+                &Self::mesh3d(model, 0..model.triangles.len()),
+            )?;
+        }
 
         // Log metadata as text
         let info_text = format!(
-            "📊 Model: {}\n🔺 Triangles: {}\n📏 Bounds: {:.2} x {:.2} x {:.2}\n💾 File size: {} bytes",
+            "📊 Model: {}\n🔺 Triangles: {}\n🧩 Parts: {}\n📏 Bounds: {:.2} x {:.2} x {:.2}\n💾 File size: {} bytes",
             model.name,
             model.triangles.len(),
+            model.parts.len(),
             model.size().x,
             model.size().y,
             model.size().z,
             model.precision_info.file_size_bytes
         );
 
-        self.rec
-            .log("cad_model/info", &rr::TextLog::new(info_text))?;
+        self.rec.log(
+            self.model_entity(path, "info"),
+            &rr::TextLog::new(info_text),
+        )?;
 
         // Log bounding box for reference
         let bbox_min = model.bounds.min;
         let bbox_max = model.bounds.max;
 
         self.rec.log(
-            "cad_model/bounding_box",
+            self.model_entity(path, "bounding_box"),
             &rr::Boxes3D::from_centers_and_sizes(
                 [[
                     (bbox_min.x + bbox_max.x) / 2.0,
@@ -145,13 +326,47 @@ impl RerunRenderer {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::cad_data::{CadModel, Triangle};
-    use glam::Vec3;
 
     #[tokio::test]
     async fn test_rerun_renderer_creation() {
-        let current_model: Arc<RwLock<Option<CadModel>>> = Arc::new(RwLock::new(None));
+        let _models: ModelRegistry = Default::default();
         // This would normally spawn the Rerun viewer, so we skip in tests
-        // let _renderer = RerunRenderer::new(current_model);
+        // let _renderer = RerunRenderer::new(models, events);
+    }
+
+    #[test]
+    fn test_entity_root_keeps_relative_path_and_extension() {
+        let root =
+            |path: &str| RerunRenderer::entity_root(Some(Path::new("/parts")), Path::new(path));
+
+        assert_eq!(
+            root("/parts/bracket v2.stl"),
+            rr::entity_path!("cad_model", "bracket v2.stl")
+        );
+        assert_ne!(root("/parts/part.stl"), root("/parts/part.obj"));
+        assert_eq!(
+            root("/parts/a/part.stl"),
+            rr::entity_path!("cad_model", "a", "part.stl")
+        );
+        assert_eq!(
+            root("/parts/bundle.zip/part.stl"),
+            rr::entity_path!("cad_model", "bundle.zip", "part.stl")
+        );
+        // Outside the base directory the whole path is used
+        assert_eq!(
+            root("/tmp/part.stl"),
+            rr::entity_path!("cad_model", "tmp", "part.stl")
+        );
+    }
+
+    #[test]
+    fn test_part_entity_names_are_unique() {
+        let part = |name: &str| MeshPart {
+            name: name.to_string(),
+            triangles: 0..1,
+        };
+        let names =
+            RerunRenderer::part_entity_names(&[part("body"), part(""), part("body"), part("lid")]);
+        assert_eq!(names, ["body", "part_1", "body_2", "lid"]);
     }
 }
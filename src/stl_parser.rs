@@ -1,12 +1,163 @@
-use anyhow::{anyhow, Context, Result};
+use bytemuck::{Pod, Zeroable};
 use glam::Vec3;
-use std::io::{Cursor, Read};
+use memmap2::Mmap;
+use rayon::prelude::*;
+use std::borrow::Cow;
+use std::iter::Peekable;
 use std::path::Path;
 
-use crate::cad_data::{CadModel, Triangle};
+use crate::cad_data::{CadModel, MeshPart, Rgba, Triangle, DEFAULT_COLOR};
+use crate::error::{CadStreamError, Result};
+use crate::parsers::{ParseMode, ParseOptions, ParseReport, SourceLocation};
+
+/// Number of leading bytes inspected when guessing the STL encoding
+pub const ENCODING_PROBE_LEN: usize = 1024;
+
+/// The two STL flavours
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StlEncoding {
+    #[default]
+    Binary,
+    Ascii,
+}
+
+impl StlEncoding {
+    fn other(self) -> Self {
+        match self {
+            StlEncoding::Binary => StlEncoding::Ascii,
+            StlEncoding::Ascii => StlEncoding::Binary,
+        }
+    }
+}
+
+/// ASCII inputs smaller than this are parsed on the calling thread
+const PARALLEL_ASCII_MIN_LEN: usize = 1 << 20;
+
+/// Facets of one ASCII chunk plus where solids start and end, as triangle indices
+/// relative to the chunk
+struct AsciiChunk {
+    triangles: Vec<Triangle>,
+    markers: Vec<SolidMarker>,
+    /// First facet before any marker, to point at facets outside of a solid
+    first_facet: Option<SourceLocation>,
+    warnings: Vec<CadStreamError>,
+}
+
+struct SolidMarker {
+    kind: SolidMarkerKind,
+    at: usize,
+    location: SourceLocation,
+    /// First facet after this marker
+    next_facet: Option<SourceLocation>,
+}
+
+enum SolidMarkerKind {
+    Start(String),
+    End,
+}
+
+/// A trimmed, non-empty ASCII STL line
+///
+/// Shared with `StlStreamReader`, so buffered and streamed files are read the same way.
+pub(crate) struct AsciiLine<'a> {
+    pub(crate) number: usize,
+    /// 1-based column of the first non-blank character
+    pub(crate) column: usize,
+    pub(crate) text: Cow<'a, str>,
+}
+
+impl<'a> AsciiLine<'a> {
+    /// Trim line `number` of the input, `None` if it is blank
+    pub(crate) fn new(number: usize, raw: &'a [u8]) -> Option<Self> {
+        let start = raw.trim_ascii_start();
+        let text = start.trim_ascii_end();
+        (!text.is_empty()).then(|| AsciiLine {
+            number,
+            column: 1 + raw.len() - start.len(),
+            text: String::from_utf8_lossy(text),
+        })
+    }
+
+    pub(crate) fn into_owned(self) -> AsciiLine<'static> {
+        AsciiLine {
+            number: self.number,
+            column: self.column,
+            text: Cow::Owned(self.text.into_owned()),
+        }
+    }
+
+    pub(crate) fn location(&self, offset: usize) -> SourceLocation {
+        SourceLocation::Line {
+            line: self.number,
+            column: self.column + offset,
+        }
+    }
+
+    fn error(&self, error: LineError) -> CadStreamError {
+        error.at_line(self.number, self.column)
+    }
+}
+
+/// A malformed `facet normal` or `vertex` line, columns are byte offsets in the line
+#[derive(Debug)]
+enum LineError {
+    InvalidNumber { column: usize, token: String },
+    Malformed { column: usize, message: String },
+}
+
+impl LineError {
+    /// Locate the error on line `line`, whose first non-blank character is at `column`
+    fn at_line(self, line: usize, column: usize) -> CadStreamError {
+        let at = |offset: usize| SourceLocation::Line {
+            line,
+            column: column + offset,
+        };
+        match self {
+            LineError::InvalidNumber {
+                column: offset,
+                token,
+            } => CadStreamError::InvalidNumber {
+                location: Some(at(offset)),
+                token,
+            },
+            LineError::Malformed {
+                column: offset,
+                message,
+            } => CadStreamError::malformed(message).at(at(offset)),
+        }
+    }
+}
+
+/// One 50-byte binary STL facet record
+///
+/// Every field is a byte array, so the struct has an alignment of 1 and can be cast
+/// from any offset of a mapped file.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+struct BinaryFacet {
+    normal: [[u8; 4]; 3],
+    vertices: [[[u8; 4]; 3]; 3],
+    attribute: [u8; 2],
+}
+
+impl BinaryFacet {
+    fn to_triangle(self) -> Triangle {
+        let vec3 = |v: [[u8; 4]; 3]| Vec3::from_array(v.map(f32::from_le_bytes));
+        Triangle {
+            vertices: self.vertices.map(vec3),
+            normal: vec3(self.normal),
+        }
+    }
+}
 
 pub struct StlParser;
 
+impl Default for StlParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl StlParser {
     pub fn new() -> Self {
         Self
@@ -15,7 +166,7 @@ impl StlParser {
     pub async fn parse_file<P: AsRef<Path>>(&self, path: P) -> Result<CadModel> {
         let data = tokio::fs::read(&path)
             .await
-            .with_context(|| format!("Failed to read file: {}", path.as_ref().display()))?;
+            .map_err(|error| CadStreamError::io(path.as_ref(), error))?;
 
         let file_name = path
             .as_ref()
@@ -27,159 +178,587 @@ impl StlParser {
         self.parse_data(&data, file_name)
     }
 
+    /// Parse a file through a read-only memory map instead of reading it into memory
+    ///
+    /// Binary facets are converted in parallel straight from the mapping. The file must
+    /// not be truncated by another process while it is being parsed.
+    pub fn parse_file_mmap<P: AsRef<Path>>(&self, path: P) -> Result<CadModel> {
+        let path = path.as_ref();
+        let file = std::fs::File::open(path).map_err(|error| CadStreamError::io(path, error))?;
+        // SAFETY: the mapping is read-only and dropped before returning, see the doc comment
+        let data = unsafe { Mmap::map(&file) }.map_err(|error| CadStreamError::io(path, error))?;
+
+        let name = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or("unknown")
+            .to_string();
+
+        self.parse_data(&data, name)
+    }
+
     pub fn parse_data(&self, data: &[u8], name: String) -> Result<CadModel> {
-        if data.len() < 80 {
-            return Err(anyhow!("File too small to be a valid STL"));
+        Ok(self
+            .parse_data_with_options(data, name, &ParseOptions::default())?
+            .0)
+    }
+
+    /// Parse STL data, in lenient mode skipping malformed facets and solids and
+    /// returning what was skipped
+    pub fn parse_data_with_options(
+        &self,
+        data: &[u8],
+        name: String,
+        options: &ParseOptions,
+    ) -> Result<(CadModel, ParseReport)> {
+        // An empty ASCII solid fits in fewer bytes than a binary header
+        if data.len() < 80 && Self::detect_encoding(data) == StlEncoding::Binary {
+            return Err(CadStreamError::truncated(
+                "File too small to be a valid STL",
+            ));
         }
 
-        // Check if it's ASCII STL by looking for "solid" at the beginning
-        let is_ascii =
-            data.starts_with(b"solid") && data.iter().take(1024).all(|&b| b.is_ascii() && b != 0);
+        let detected = Self::detect_encoding(data);
+        let (mut model, report) = Self::parse_with_fallback(data, |encoding| {
+            // Recovering facets from the wrong encoding only decodes garbage, e.g. ASCII
+            // text read as binary records, so the fallback attempt is always strict
+            let mode = if encoding == detected {
+                options.mode
+            } else {
+                ParseMode::Strict
+            };
 
-        let triangles = if is_ascii {
-            self.parse_ascii_stl(data)?
-        } else {
-            self.parse_binary_stl(data)?
-        };
+            let mut report = ParseReport::default();
+            let model = match encoding {
+                StlEncoding::Ascii => {
+                    let (triangles, parts) = self.parse_ascii_stl(data, mode, &mut report)?;
+                    let mut model = CadModel::new(name.clone(), triangles);
+                    model.parts = parts;
+                    model
+                }
+                StlEncoding::Binary => {
+                    let triangles = self.parse_binary_stl(data, mode, &mut report)?;
+                    let count = triangles.len();
+                    let mut model = CadModel::new(name.clone(), triangles);
+                    model.vertex_colors = Self::binary_facet_colors(data)
+                        .map(|colors| colors.into_iter().take(count).map(|c| [c; 3]).collect());
+                    model
+                }
+            };
+
+            // Recovering nothing at all means this is probably the other encoding
+            if model.triangles.is_empty() && !report.warnings.is_empty() {
+                return Err(report.warnings.swap_remove(0));
+            }
+            Ok((model, report))
+        })?;
 
-        println!("📐 Parsed {} triangles from {}", triangles.len(), name);
+        if model.parts.len() > 1 {
+            println!(
+                "📐 Parsed {} triangles in {} solids from {}",
+                model.triangles.len(),
+                model.parts.len(),
+                name
+            );
+        } else {
+            println!(
+                "📐 Parsed {} triangles from {}",
+                model.triangles.len(),
+                name
+            );
+        }
+        if !report.warnings.is_empty() {
+            println!(
+                "⚠️  Skipped {} malformed elements in {}",
+                report.warnings.len(),
+                name
+            );
+        }
 
-        let mut model = CadModel::new(name, triangles);
         model.precision_info.file_size_bytes = data.len();
 
-        Ok(model)
+        Ok((model, report))
     }
 
-    fn parse_ascii_stl(&self, data: &[u8]) -> Result<Vec<Triangle>> {
-        let content = String::from_utf8_lossy(data);
-        let mut triangles = Vec::new();
-        let mut lines = content.lines().map(|l| l.trim()).filter(|l| !l.is_empty());
+    /// Guess whether `data` is binary or ASCII STL
+    ///
+    /// Many exporters write binary files whose header starts with "solid", so a file whose
+    /// length matches `84 + 50 * facet_count` is treated as binary unless it is plain text.
+    pub fn detect_encoding(data: &[u8]) -> StlEncoding {
+        Self::detect_encoding_from_prefix(
+            &data[..data.len().min(ENCODING_PROBE_LEN)],
+            Some(data.len() as u64),
+        )
+    }
 
-        // Skip the "solid" line
-        if let Some(line) = lines.next() {
-            if !line.starts_with("solid") {
-                return Err(anyhow!("Invalid ASCII STL: missing 'solid' header"));
-            }
+    /// Like [`StlParser::detect_encoding`] when only the first [`ENCODING_PROBE_LEN`]
+    /// bytes and possibly the total length are known, e.g. while streaming
+    pub fn detect_encoding_from_prefix(prefix: &[u8], total_len: Option<u64>) -> StlEncoding {
+        let is_text = prefix.iter().all(|&b| b.is_ascii() && b != 0);
+
+        let binary_size_matches = prefix.len() >= 84
+            && total_len.is_some_and(|len| {
+                let facet_count =
+                    u32::from_le_bytes([prefix[80], prefix[81], prefix[82], prefix[83]]);
+                84 + 50 * facet_count as u64 == len
+            });
+
+        if binary_size_matches && !is_text {
+            StlEncoding::Binary
+        } else if prefix.starts_with(b"solid") && is_text {
+            StlEncoding::Ascii
+        } else {
+            StlEncoding::Binary
         }
+    }
 
-        while let Some(line) = lines.next() {
-            if line.starts_with("endsolid") {
-                break;
-            }
+    /// Run `parse` with the detected encoding, retrying with the other one if it fails
+    pub fn parse_with_fallback<T>(
+        data: &[u8],
+        mut parse: impl FnMut(StlEncoding) -> Result<T>,
+    ) -> Result<T> {
+        let encoding = Self::detect_encoding(data);
+
+        // The detected encoding's error is the one that describes the file
+        parse(encoding).or_else(|first_error| parse(encoding.other()).map_err(|_| first_error))
+    }
 
-            if line.starts_with("facet normal") {
-                let normal = self.parse_normal_line(line)?;
+    /// Decode per-facet colors stored in the attribute bytes of binary STL data
+    ///
+    /// Materialise Magics files carry `COLOR=` plus a default RGBA in the header and
+    /// store red in the low bits, with bit 15 set meaning "use the default color".
+    /// VisCAM/SolidView files store blue in the low bits, with bit 15 set meaning
+    /// "this color is valid". Returns `None` if no facet carries a color.
+    ///
+    /// Only complete facets are read, so a truncated file gets as many colors as it
+    /// has triangles whatever count its header declares.
+    pub fn binary_facet_colors(data: &[u8]) -> Option<Vec<Rgba>> {
+        let header = data.get(..80)?;
+        let declared = u32::from_le_bytes(data.get(80..84)?.try_into().ok()?) as usize;
+        let facet_count = declared.min((data.len() - 84) / size_of::<BinaryFacet>());
+        let attributes = (0..facet_count).map(|i| {
+            let offset = 84 + i * size_of::<BinaryFacet>() + 48;
+            u16::from_le_bytes([data[offset], data[offset + 1]])
+        });
 
-                // Skip "outer loop"
-                if let Some(loop_line) = lines.next() {
-                    if !loop_line.starts_with("outer loop") {
-                        return Err(anyhow!("Expected 'outer loop', found: {}", loop_line));
-                    }
-                }
+        let magics_default = header
+            .windows(6)
+            .position(|w| w == b"COLOR=")
+            .and_then(|pos| header.get(pos + 6..pos + 10))
+            .map(|rgba| [rgba[0], rgba[1], rgba[2], rgba[3]]);
 
-                // Parse three vertices
-                let mut vertices = [Vec3::ZERO; 3];
-                for i in 0..3 {
-                    if let Some(vertex_line) = lines.next() {
-                        vertices[i] = self.parse_vertex_line(vertex_line)?;
+        if let Some(default_color) = magics_default {
+            return Some(
+                attributes
+                    .map(|attribute| {
+                        if attribute & 0x8000 != 0 {
+                            default_color
+                        } else {
+                            let [r, g, b] = Self::unpack_rgb15(attribute);
+                            [r, g, b, 255]
+                        }
+                    })
+                    .collect(),
+            );
+        }
+
+        let attributes: Vec<u16> = attributes.collect();
+        if !attributes.iter().any(|attribute| attribute & 0x8000 != 0) {
+            return None;
+        }
+
+        Some(
+            attributes
+                .into_iter()
+                .map(|attribute| {
+                    if attribute & 0x8000 != 0 {
+                        let [b, g, r] = Self::unpack_rgb15(attribute);
+                        [r, g, b, 255]
                     } else {
-                        return Err(anyhow!("Missing vertex in triangle"));
+                        DEFAULT_COLOR
                     }
+                })
+                .collect(),
+        )
+    }
+
+    /// Split 5-bit channels (low bits first) and expand them to 8 bits
+    fn unpack_rgb15(attribute: u16) -> [u8; 3] {
+        [0, 5, 10].map(|shift| {
+            let channel = ((attribute >> shift) & 0x1f) as u8;
+            (channel << 3) | (channel >> 2)
+        })
+    }
+
+    /// Parse every `solid ... endsolid` block, each one becomes a part named after it
+    ///
+    /// Large inputs are split on `facet` boundaries and the chunks parsed in parallel,
+    /// then stitched back together in order.
+    fn parse_ascii_stl(
+        &self,
+        data: &[u8],
+        mode: ParseMode,
+        report: &mut ParseReport,
+    ) -> Result<(Vec<Triangle>, Vec<MeshPart>)> {
+        let first_line = data
+            .split(|&b| b == b'\n')
+            .map(|line| line.trim_ascii())
+            .find(|line| !line.is_empty());
+        if !first_line.is_some_and(|line| line.starts_with(b"solid")) {
+            return Err(CadStreamError::malformed(
+                "Invalid ASCII STL: missing 'solid' header",
+            ));
+        }
+
+        let chunks = Self::split_ascii_chunks(data, rayon::current_num_threads());
+        let newlines: Vec<usize> = chunks
+            .par_iter()
+            .map(|chunk| chunk.iter().filter(|&&b| b == b'\n').count())
+            .collect();
+        let first_lines = newlines.iter().scan(1, |line, count| {
+            let first = *line;
+            *line += count;
+            Some(first)
+        });
+        let chunks: Vec<(&[u8], usize)> = chunks.into_iter().zip(first_lines).collect();
+        let parsed: Vec<Result<AsciiChunk>> = chunks
+            .par_iter()
+            .map(|&(chunk, first_line)| self.parse_ascii_chunk(chunk, first_line, mode))
+            .collect();
+
+        let mut triangles = Vec::new();
+        let mut parts = Vec::new();
+        // Name, first triangle and `solid` line of the solid currently being read
+        let mut current_solid: Option<(String, usize, SourceLocation)> = None;
+        let outside_solid =
+            |location| CadStreamError::malformed("Facet outside of a solid").at(location);
+
+        // Errors are reported in file order, not in the order the threads hit them
+        for chunk in parsed {
+            let chunk = chunk?;
+            report.warnings.extend(chunk.warnings);
+            let base = triangles.len();
+            let mut checked = 0;
+            let mut next_facet = chunk.first_facet;
+
+            for marker in chunk.markers {
+                if marker.at > checked && current_solid.is_none() {
+                    report.record(mode, outside_solid(next_facet.unwrap_or(marker.location)))?;
                 }
+                checked = marker.at;
+                next_facet = marker.next_facet;
 
-                // Skip "endloop" and "endfacet"
-                lines.next(); // endloop
-                lines.next(); // endfacet
+                match marker.kind {
+                    SolidMarkerKind::Start(name) => {
+                        if let Some((open_name, start, _)) = current_solid.take() {
+                            report.record(
+                                mode,
+                                CadStreamError::malformed("'solid' before 'endsolid'")
+                                    .at(marker.location),
+                            )?;
+                            parts.push(MeshPart {
+                                name: open_name,
+                                triangles: start..base + marker.at,
+                            });
+                        }
+                        current_solid = Some((name, base + marker.at, marker.location));
+                    }
+                    SolidMarkerKind::End => match current_solid.take() {
+                        Some((name, start, _)) => parts.push(MeshPart {
+                            name,
+                            triangles: start..base + marker.at,
+                        }),
+                        None => report.record(
+                            mode,
+                            CadStreamError::malformed("'endsolid' without 'solid'")
+                                .at(marker.location),
+                        )?,
+                    },
+                }
+            }
 
-                triangles.push(Triangle { vertices, normal });
+            if chunk.triangles.len() > checked && current_solid.is_none() {
+                if let Some(location) = next_facet {
+                    report.record(mode, outside_solid(location))?;
+                }
             }
+            triangles.extend(chunk.triangles);
         }
 
-        Ok(triangles)
+        if let Some((name, start, location)) = current_solid {
+            report.record(
+                mode,
+                CadStreamError::malformed("'solid' is never closed by 'endsolid'").at(location),
+            )?;
+            parts.push(MeshPart {
+                name,
+                triangles: start..triangles.len(),
+            });
+        }
+
+        Ok((triangles, parts))
     }
 
-    fn parse_binary_stl(&self, data: &[u8]) -> Result<Vec<Triangle>> {
-        if data.len() < 84 {
-            return Err(anyhow!("Binary STL too small"));
+    /// Split ASCII STL data into about `count` chunks, each after the first starting at
+    /// a `facet` line
+    fn split_ascii_chunks(data: &[u8], count: usize) -> Vec<&[u8]> {
+        if data.len() < PARALLEL_ASCII_MIN_LEN || count < 2 {
+            return vec![data];
         }
 
-        let mut cursor = Cursor::new(data);
+        let target_len = data.len() / count;
+        let mut chunks = Vec::with_capacity(count);
+        let mut start = 0;
 
-        // Skip 80-byte header
-        cursor.set_position(80);
+        for index in 1..count {
+            let mut position = (index * target_len).max(start);
 
-        // Read triangle count
-        let triangle_count = self.read_u32_le(&mut cursor)?;
+            // Move to the start of the next line beginning with "facet"
+            let boundary = loop {
+                let Some(newline) = data[position..].iter().position(|&b| b == b'\n') else {
+                    break None;
+                };
+                position += newline + 1;
+                if data[position..].trim_ascii_start().starts_with(b"facet") {
+                    break Some(position);
+                }
+            };
 
-        if data.len() < 84 + (triangle_count as usize * 50) {
-            return Err(anyhow!("Binary STL data truncated"));
+            match boundary {
+                Some(boundary) => {
+                    chunks.push(&data[start..boundary]);
+                    start = boundary;
+                }
+                None => break,
+            }
         }
 
-        let mut triangles = Vec::with_capacity(triangle_count as usize);
+        chunks.push(&data[start..]);
+        chunks
+    }
+
+    /// Parse the facets of one chunk, recording where solids start and end
+    ///
+    /// `first_line` is the line number the chunk starts at within the whole file.
+    fn parse_ascii_chunk(
+        &self,
+        chunk: &[u8],
+        first_line: usize,
+        mode: ParseMode,
+    ) -> Result<AsciiChunk> {
+        let mut triangles = Vec::new();
+        let mut markers: Vec<SolidMarker> = Vec::new();
+        let mut first_facet = None;
+        let mut report = ParseReport::default();
+        let mut lines = chunk
+            .split(|&b| b == b'\n')
+            .enumerate()
+            .filter_map(|(index, raw)| AsciiLine::new(first_line + index, raw))
+            .peekable();
+        // Whether the remaining lines of a skipped facet are being passed over
+        let mut skipping = false;
 
-        for _ in 0..triangle_count {
-            // Read normal
-            let normal = Vec3::new(
-                self.read_f32_le(&mut cursor)?,
-                self.read_f32_le(&mut cursor)?,
-                self.read_f32_le(&mut cursor)?,
-            );
+        while let Some(line) = lines.next() {
+            let kind = if line.text.starts_with("endsolid") {
+                Some(SolidMarkerKind::End)
+            } else if line.text.starts_with("solid") {
+                Some(SolidMarkerKind::Start(self.solid_name(&line.text)))
+            } else {
+                None
+            };
 
-            // Read vertices
-            let mut vertices = [Vec3::ZERO; 3];
-            for i in 0..3 {
-                vertices[i] = Vec3::new(
-                    self.read_f32_le(&mut cursor)?,
-                    self.read_f32_le(&mut cursor)?,
-                    self.read_f32_le(&mut cursor)?,
-                );
+            if let Some(kind) = kind {
+                markers.push(SolidMarker {
+                    kind,
+                    at: triangles.len(),
+                    location: line.location(0),
+                    next_facet: None,
+                });
+                skipping = false;
+            } else if line.text.starts_with("facet") {
+                skipping = false;
+                match self.parse_ascii_facet(&line, &mut lines) {
+                    Ok(triangle) => {
+                        let slot = match markers.last_mut() {
+                            Some(marker) => &mut marker.next_facet,
+                            None => &mut first_facet,
+                        };
+                        slot.get_or_insert(line.location(0));
+                        triangles.push(triangle);
+                    }
+                    Err(error) => {
+                        report.record(mode, error)?;
+                        skipping = true;
+                    }
+                }
+            } else if !skipping {
+                report.record(
+                    mode,
+                    CadStreamError::malformed(format!("Unexpected line: {}", line.text))
+                        .at(line.location(0)),
+                )?;
             }
+        }
+
+        Ok(AsciiChunk {
+            triangles,
+            markers,
+            first_facet,
+            warnings: report.warnings,
+        })
+    }
+
+    /// Parse one `facet ... endfacet` block starting at `facet_line`
+    ///
+    /// On error the offending line is left in `lines`, so it can start the next facet.
+    pub(crate) fn parse_ascii_facet<'a>(
+        &self,
+        facet_line: &AsciiLine<'a>,
+        lines: &mut Peekable<impl Iterator<Item = AsciiLine<'a>>>,
+    ) -> Result<Triangle> {
+        let normal = self
+            .parse_normal_line(&facet_line.text)
+            .map_err(|error| facet_line.error(error))?;
+
+        Self::expect_keyword(facet_line, lines, "outer loop")?;
+
+        let mut vertices = [Vec3::ZERO; 3];
+        for vertex in &mut vertices {
+            let line = Self::expect_keyword(facet_line, lines, "vertex")?;
+            *vertex = self
+                .parse_vertex_line(&line.text)
+                .map_err(|error| line.error(error))?;
+        }
+
+        Self::expect_keyword(facet_line, lines, "endloop")?;
+        Self::expect_keyword(facet_line, lines, "endfacet")?;
 
-            // Skip attribute byte count
-            cursor.set_position(cursor.position() + 2);
+        Ok(Triangle { vertices, normal })
+    }
 
-            triangles.push(Triangle { vertices, normal });
+    /// Take the next line if it starts with `keyword`
+    fn expect_keyword<'a>(
+        facet_line: &AsciiLine<'a>,
+        lines: &mut Peekable<impl Iterator<Item = AsciiLine<'a>>>,
+        keyword: &str,
+    ) -> Result<AsciiLine<'a>> {
+        if let Some(line) = lines.next_if(|line| line.text.starts_with(keyword)) {
+            return Ok(line);
         }
 
-        Ok(triangles)
+        Err(match lines.peek() {
+            Some(line) => {
+                CadStreamError::malformed(format!("Expected '{}', found: {}", keyword, line.text))
+                    .at(line.location(0))
+            }
+            None => CadStreamError::malformed(format!("Facet is missing '{}'", keyword))
+                .at(facet_line.location(0)),
+        })
     }
 
-    fn parse_normal_line(&self, line: &str) -> Result<Vec3> {
-        let parts: Vec<&str> = line.split_whitespace().collect();
-        if parts.len() != 5 || parts[0] != "facet" || parts[1] != "normal" {
-            return Err(anyhow!("Invalid normal line: {}", line));
+    pub(crate) fn solid_name(&self, line: &str) -> String {
+        line["solid".len()..].trim().to_string()
+    }
+
+    /// View the facet records in place and convert them on the rayon thread pool
+    ///
+    /// A truncated file is an error in strict mode, in lenient mode its complete
+    /// facets are kept.
+    fn parse_binary_stl(
+        &self,
+        data: &[u8],
+        mode: ParseMode,
+        report: &mut ParseReport,
+    ) -> Result<Vec<Triangle>> {
+        if data.len() < 84 {
+            return Err(CadStreamError::truncated("Binary STL too small")
+                .at(SourceLocation::ByteOffset(data.len())));
         }
 
-        Ok(Vec3::new(
-            parts[2].parse::<f32>()?,
-            parts[3].parse::<f32>()?,
-            parts[4].parse::<f32>()?,
-        ))
+        let declared = u32::from_le_bytes([data[80], data[81], data[82], data[83]]) as usize;
+        let available = (data.len() - 84) / size_of::<BinaryFacet>();
+        let triangle_count = if available < declared {
+            report.record(
+                mode,
+                CadStreamError::truncated(format!(
+                    "header declares {} facets, {} are complete",
+                    declared, available
+                ))
+                .at(SourceLocation::ByteOffset(
+                    84 + available * size_of::<BinaryFacet>(),
+                )),
+            )?;
+            available
+        } else {
+            declared
+        };
+
+        let records = &data[84..84 + triangle_count * size_of::<BinaryFacet>()];
+        let facets: &[BinaryFacet] = bytemuck::cast_slice(records);
+
+        Ok(facets.par_iter().map(|facet| facet.to_triangle()).collect())
+    }
+
+    fn parse_normal_line(&self, line: &str) -> Result<Vec3, LineError> {
+        let mut tokens = Self::tokens(line);
+        match (tokens.next(), tokens.next()) {
+            (Some((_, "facet")), Some((_, "normal"))) => self.parse_vec3_tokens(line, tokens),
+            _ => Err(LineError::Malformed {
+                column: 0,
+                message: format!("Expected 'facet normal', found: {}", line),
+            }),
+        }
     }
 
-    fn parse_vertex_line(&self, line: &str) -> Result<Vec3> {
-        let parts: Vec<&str> = line.split_whitespace().collect();
-        if parts.len() != 4 || parts[0] != "vertex" {
-            return Err(anyhow!("Invalid vertex line: {}", line));
+    fn parse_vertex_line(&self, line: &str) -> Result<Vec3, LineError> {
+        let mut tokens = Self::tokens(line);
+        match tokens.next() {
+            Some((_, "vertex")) => self.parse_vec3_tokens(line, tokens),
+            _ => Err(LineError::Malformed {
+                column: 0,
+                message: format!("Expected 'vertex', found: {}", line),
+            }),
         }
+    }
 
-        Ok(Vec3::new(
-            parts[1].parse::<f32>()?,
-            parts[2].parse::<f32>()?,
-            parts[3].parse::<f32>()?,
-        ))
+    /// Whitespace separated tokens with their byte offset in `line`
+    fn tokens(line: &str) -> impl Iterator<Item = (usize, &str)> {
+        line.split_ascii_whitespace()
+            .map(move |token| (token.as_ptr() as usize - line.as_ptr() as usize, token))
     }
 
-    fn read_u32_le(&self, cursor: &mut Cursor<&[u8]>) -> Result<u32> {
-        let mut buf = [0u8; 4];
-        cursor.read_exact(&mut buf)?;
-        Ok(u32::from_le_bytes(buf))
+    /// Exactly three floats and nothing after them
+    fn parse_vec3_tokens<'a>(
+        &self,
+        line: &str,
+        mut tokens: impl Iterator<Item = (usize, &'a str)>,
+    ) -> Result<Vec3, LineError> {
+        let mut components = [0.0; 3];
+        for component in &mut components {
+            let (column, token) = tokens.next().ok_or_else(|| LineError::Malformed {
+                column: line.len(),
+                message: format!("Expected 3 coordinates in: {}", line),
+            })?;
+            *component = Self::parse_f32(token).ok_or_else(|| LineError::InvalidNumber {
+                column,
+                token: token.to_string(),
+            })?;
+        }
+
+        match tokens.next() {
+            Some((column, token)) => Err(LineError::Malformed {
+                column,
+                message: format!("Unexpected '{}' after the coordinates", token),
+            }),
+            None => Ok(Vec3::from_array(components)),
+        }
     }
 
-    fn read_f32_le(&self, cursor: &mut Cursor<&[u8]>) -> Result<f32> {
-        let mut buf = [0u8; 4];
-        cursor.read_exact(&mut buf)?;
-        Ok(f32::from_le_bytes(buf))
+    fn parse_f32(token: &str) -> Option<f32> {
+        // lexical is much faster than `str::parse` but spells some special values differently
+        lexical_core::parse::<f32>(token.as_bytes())
+            .ok()
+            .or_else(|| token.parse().ok())
     }
 }
 
@@ -208,4 +787,338 @@ endsolid test";
         assert_eq!(model.triangles[0].vertices[2], Vec3::new(0.0, 1.0, 0.0));
         assert_eq!(model.triangles[0].normal, Vec3::new(0.0, 0.0, 1.0));
     }
+
+    #[test]
+    fn test_parse_multi_solid_ascii_stl() {
+        let stl_data = b"solid base
+facet normal 0 0 1
+outer loop
+vertex 0 0 0
+vertex 1 0 0
+vertex 0 1 0
+endloop
+endfacet
+endsolid base
+solid lid part
+facet normal 0 0 1
+outer loop
+vertex 0 0 1
+vertex 1 0 1
+vertex 0 1 1
+endloop
+endfacet
+facet normal 0 0 1
+outer loop
+vertex 1 0 1
+vertex 1 1 1
+vertex 0 1 1
+endloop
+endfacet
+endsolid lid part
+";
+
+        let model = StlParser::new()
+            .parse_data(stl_data, "assembly.stl".to_string())
+            .unwrap();
+
+        assert_eq!(model.triangles.len(), 3);
+        assert_eq!(
+            model.parts,
+            vec![
+                MeshPart {
+                    name: "base".to_string(),
+                    triangles: 0..1,
+                },
+                MeshPart {
+                    name: "lid part".to_string(),
+                    triangles: 1..3,
+                },
+            ]
+        );
+        assert_eq!(model.triangles[2].vertices[1], Vec3::new(1.0, 1.0, 1.0));
+
+        let truncated = &stl_data[..stl_data.len() - "endsolid lid part\n".len()];
+        assert!(StlParser::new()
+            .parse_ascii_stl(truncated, ParseMode::Strict, &mut ParseReport::default())
+            .unwrap_err()
+            .to_string()
+            .contains("endsolid"));
+    }
+
+    fn binary_stl(header: &[u8], attributes: &[u16]) -> Vec<u8> {
+        let mut data = header.to_vec();
+        data.resize(80, 0);
+        data.extend_from_slice(&(attributes.len() as u32).to_le_bytes());
+        for attribute in attributes {
+            data.extend_from_slice(&[0; 48]);
+            data.extend_from_slice(&attribute.to_le_bytes());
+        }
+        data
+    }
+
+    #[test]
+    fn test_binary_facet_colors() {
+        // VisCAM: bit 15 marks a valid color, blue in the low bits
+        let data = binary_stl(b"plain", &[0x8000 | 0x1f, 0x8000 | (0x1f << 10), 0]);
+        let model = StlParser::new()
+            .parse_data(&data, "viscam.stl".to_string())
+            .unwrap();
+        let colors = model.vertex_colors.unwrap();
+        assert_eq!(colors[0], [[0, 0, 255, 255]; 3]);
+        assert_eq!(colors[1][0], [255, 0, 0, 255]);
+        assert_eq!(colors[2][0], DEFAULT_COLOR);
+
+        // Magics: red in the low bits, bit 15 selects the header's default color
+        let data = binary_stl(b"COLOR=\x10\x20\x30\xff", &[0x1f, 0x8000]);
+        let colors = StlParser::binary_facet_colors(&data).unwrap();
+        assert_eq!(colors, vec![[255, 0, 0, 255], [0x10, 0x20, 0x30, 0xff]]);
+
+        // Plain binary STLs stay uncolored
+        assert!(StlParser::binary_facet_colors(&binary_stl(b"plain", &[0, 0])).is_none());
+
+        // A truncated file claiming 2^32 - 1 facets only gets colors for the ones present
+        let mut data = binary_stl(b"plain", &[0x8000 | 0x1f, 0x8000]);
+        data[80..84].copy_from_slice(&u32::MAX.to_le_bytes());
+        data.truncate(data.len() - 10);
+        assert_eq!(StlParser::binary_facet_colors(&data).unwrap().len(), 1);
+        let lenient = ParseOptions {
+            mode: ParseMode::Lenient,
+            ..Default::default()
+        };
+        let (model, report) = StlParser::new()
+            .parse_data_with_options(&data, "huge.stl".to_string(), &lenient)
+            .unwrap();
+        assert_eq!(model.vertex_colors.unwrap().len(), 1);
+        assert_eq!(report.warnings.len(), 1);
+    }
+
+    #[test]
+    fn test_mmap_matches_in_memory_parse() {
+        let mut data = binary_stl(b"COLOR=\x10\x20\x30\xff", &[0x1f, 0x8000, 0]);
+        for (i, byte) in data[84..].iter_mut().enumerate() {
+            // Fill the float fields with distinct values, keep the attributes
+            if i % 50 < 48 {
+                *byte = (i % 251) as u8;
+            }
+        }
+        let path = std::env::temp_dir().join(format!("cadstream_mmap_{}.stl", std::process::id()));
+        std::fs::write(&path, &data).unwrap();
+
+        let parser = StlParser::new();
+        let mapped = parser.parse_file_mmap(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let parsed = parser.parse_data(&data, "mmap.stl".to_string()).unwrap();
+
+        assert_eq!(mapped.triangles.len(), 3);
+        for (a, b) in mapped.triangles.iter().zip(&parsed.triangles) {
+            assert_eq!(
+                a.vertices.map(|v| v.to_array().map(f32::to_bits)),
+                b.vertices.map(|v| v.to_array().map(f32::to_bits))
+            );
+            assert_eq!(
+                a.normal.to_array().map(f32::to_bits),
+                b.normal.to_array().map(f32::to_bits)
+            );
+        }
+        assert_eq!(mapped.vertex_colors, parsed.vertex_colors);
+    }
+
+    #[test]
+    fn test_parallel_ascii_chunks_preserve_order() {
+        let mut data = String::new();
+        for solid in 0..3 {
+            data.push_str(&format!("solid part{}\n", solid));
+            for i in 0..20_000 {
+                let x = (solid * 20_000 + i) as f32;
+                data.push_str(&format!(
+                    "  facet normal 0 0 1\n    outer loop\n      vertex {} 0 0\n      vertex {} 1 0\n      vertex {} 0 1\n    endloop\n  endfacet\n",
+                    x, x, x
+                ));
+            }
+            data.push_str(&format!("endsolid part{}\n", solid));
+        }
+        let data = data.into_bytes();
+
+        let chunks = StlParser::split_ascii_chunks(&data, 4);
+        assert_eq!(chunks.len(), 4);
+        assert_eq!(chunks.concat(), data);
+        assert!(chunks[1..]
+            .iter()
+            .all(|chunk| chunk.trim_ascii_start().starts_with(b"facet")));
+
+        let (triangles, parts) = StlParser::new()
+            .parse_ascii_stl(&data, ParseMode::Strict, &mut ParseReport::default())
+            .unwrap();
+        assert_eq!(triangles.len(), 60_000);
+        assert!(triangles
+            .iter()
+            .enumerate()
+            .all(|(i, t)| t.vertices[0].x == i as f32));
+        assert_eq!(
+            parts
+                .iter()
+                .map(|p| p.triangles.clone())
+                .collect::<Vec<_>>(),
+            vec![0..20_000, 20_000..40_000, 40_000..60_000]
+        );
+    }
+
+    #[test]
+    fn test_binary_stl_with_solid_header() {
+        // Header and float data are printable ASCII, only the size check tells this
+        // apart from an ASCII file
+        let mut data = format!("{:<80}", "solid part exported as binary").into_bytes();
+        data.extend_from_slice(&2u32.to_le_bytes());
+        for _ in 0..2 {
+            data.extend_from_slice(&b"ABCD".repeat(12));
+            data.extend_from_slice(b"  ");
+        }
+
+        assert_eq!(StlParser::detect_encoding(&data), StlEncoding::Binary);
+
+        let model = StlParser::new()
+            .parse_data(&data, "part.stl".to_string())
+            .unwrap();
+        assert_eq!(model.triangles.len(), 2);
+        assert_eq!(
+            model.triangles[1].vertices[2],
+            Vec3::splat(f32::from_le_bytes(*b"ABCD"))
+        );
+
+        // When the detected encoding fails the other one is tried
+        let ascii = b"solid test\nendsolid test\n";
+        assert_eq!(StlParser::detect_encoding(ascii), StlEncoding::Ascii);
+        let used = StlParser::parse_with_fallback(ascii, |encoding| match encoding {
+            StlEncoding::Ascii => Err(CadStreamError::malformed("broken")),
+            StlEncoding::Binary => Ok(encoding),
+        });
+        assert_eq!(used.unwrap(), StlEncoding::Binary);
+    }
+
+    #[test]
+    fn test_strict_mode_rejects_lines_outside_facets() {
+        let stl_data = b"solid test
+color 1 0 0
+facet normal 0 0 1
+  outer loop
+    vertex 0 0 0
+    vertex 1 0 0
+    vertex 0 1 0
+  endloop
+endfacet
+endsolid test
+exported by acme
+";
+        let parser = StlParser::new();
+
+        let error = parser
+            .parse_data(stl_data, "test.stl".to_string())
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "line 2, column 1: Unexpected line: color 1 0 0"
+        );
+
+        let lenient = ParseOptions {
+            mode: ParseMode::Lenient,
+            ..Default::default()
+        };
+        let (model, report) = parser
+            .parse_data_with_options(stl_data, "test.stl".to_string(), &lenient)
+            .unwrap();
+        assert_eq!(model.triangles.len(), 1);
+        assert_eq!(report.warnings.len(), 2);
+        assert_eq!(
+            report.warnings[1].location(),
+            Some(SourceLocation::Line {
+                line: 11,
+                column: 1
+            })
+        );
+    }
+
+    #[test]
+    fn test_strict_and_lenient_modes() {
+        let stl_data = b"solid test
+facet normal 0 0 1
+  outer loop
+    vertex 0 0 0
+    vertex 1 0 0
+    vertex 0 1 0
+  endloop
+endfacet
+facet normal 0 0 1
+  outer loop
+    vertex 0 0 1
+    vertex 1 x 1
+    vertex 0 1 1
+  endloop
+endfacet
+facet normal 0 0 1
+  outer loop
+    vertex 0 0 2
+    vertex 1 0 2
+    vertex 0 1 2
+  endfacet
+endsolid test
+";
+        let parser = StlParser::new();
+
+        let error = parser
+            .parse_data(stl_data, "test.stl".to_string())
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            CadStreamError::InvalidNumber {
+                location: Some(SourceLocation::Line {
+                    line: 12,
+                    column: 14
+                }),
+                ref token,
+            } if token == "x"
+        ));
+
+        let lenient = ParseOptions {
+            mode: ParseMode::Lenient,
+            ..Default::default()
+        };
+        let (model, report) = parser
+            .parse_data_with_options(stl_data, "test.stl".to_string(), &lenient)
+            .unwrap();
+        assert_eq!(model.triangles.len(), 1);
+        assert_eq!(model.parts[0].triangles, 0..1);
+        assert_eq!(report.warnings.len(), 2);
+        assert_eq!(
+            report.warnings[1].to_string(),
+            "line 21, column 3: Expected 'endloop', found: endfacet"
+        );
+
+        // Truncated binary data keeps its complete facets in lenient mode only
+        let mut data = binary_stl(b"plain", &[0, 0, 0]);
+        data.truncate(data.len() - 10);
+        let error = parser.parse_data(&data, "cut.stl".to_string()).unwrap_err();
+        assert!(matches!(error, CadStreamError::Truncated { .. }));
+        assert_eq!(error.location(), Some(SourceLocation::ByteOffset(184)));
+        let (model, report) = parser
+            .parse_data_with_options(&data, "cut.stl".to_string(), &lenient)
+            .unwrap();
+        assert_eq!(model.triangles.len(), 2);
+        assert_eq!(
+            report.warnings[0].location(),
+            Some(SourceLocation::ByteOffset(184))
+        );
+
+        // Nothing recoverable as ASCII is an error, not text decoded as binary facets
+        let garbage = "solid junk\n".to_string() + &"facet normal 0 0 nope\n".repeat(10);
+        let error = parser
+            .parse_data_with_options(garbage.as_bytes(), "junk.stl".to_string(), &lenient)
+            .unwrap_err();
+        assert_eq!(
+            error.location(),
+            Some(SourceLocation::Line {
+                line: 2,
+                column: 18
+            })
+        );
+    }
 }
@@ -0,0 +1,689 @@
+use futures::stream::{self, Stream};
+use glam::Vec3;
+use std::collections::VecDeque;
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{Context as TaskContext, Poll};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, BufReader, ReadBuf};
+
+use crate::cad_data::{CadModel, MeshPart, Triangle};
+use crate::error::{CadStreamError, Result};
+use crate::parsers::{ParseOptions, ParseReport, SourceLocation};
+use crate::stl_parser::{AsciiLine, StlEncoding, StlParser, ENCODING_PROBE_LEN};
+
+/// Default number of triangles per batch
+pub const DEFAULT_BATCH_SIZE: usize = 16 * 1024;
+
+/// Default for [`ParseOptions::max_line_length`], far beyond any real ASCII STL line
+pub const DEFAULT_MAX_LINE_LENGTH: usize = 64 * 1024;
+
+/// Size of one binary STL facet record
+const BINARY_RECORD_LEN: usize = 50;
+
+/// Lines of an ASCII facet after its `facet normal` line
+const FACET_LINES: usize = 6;
+
+/// A run of consecutive triangles read from a stream
+#[derive(Debug, Clone)]
+pub struct TriangleBatch {
+    /// Index of the first triangle of the batch within the file
+    pub first_triangle: usize,
+    pub triangles: Vec<Triangle>,
+    /// Name of the ASCII `solid` the triangles belong to, batches never span solids
+    pub solid: Option<String>,
+}
+
+/// Replays the bytes read for encoding detection before the rest of the stream
+struct Probed<R> {
+    prefix: Vec<u8>,
+    position: usize,
+    inner: R,
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for Probed<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        if self.position < self.prefix.len() {
+            let len = buf.remaining().min(self.prefix.len() - self.position);
+            buf.put_slice(&self.prefix[self.position..self.position + len]);
+            self.position += len;
+            return Poll::Ready(Ok(()));
+        }
+
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+/// Incremental STL reader that yields triangle batches without buffering the whole file
+///
+/// Unlike `StlParser::parse_data` the encoding is chosen once from the first bytes
+/// (and the total length when known), since the stream can't be rewound to retry.
+/// ASCII lines and facets are read by the same code as `StlParser`, and lenient
+/// [`ParseOptions`] skip the same problems, see [`StlStreamReader::report`].
+/// Lines longer than [`ParseOptions::line_length_limit`] are an error in either mode.
+/// Facet colors are not decoded.
+pub struct StlStreamReader<R> {
+    reader: BufReader<Probed<R>>,
+    encoding: StlEncoding,
+    batch_size: usize,
+    options: ParseOptions,
+    report: ParseReport,
+    triangles_read: usize,
+    /// Bytes consumed from the stream, including the probed prefix
+    bytes_read: usize,
+    /// Number of the last ASCII line read from `reader`
+    line: usize,
+    /// ASCII lines read ahead of the one being parsed
+    pending: VecDeque<AsciiLine<'static>>,
+    state: ReadState,
+}
+
+enum ReadState {
+    Binary { remaining: usize },
+    Ascii(AsciiState),
+    Done,
+}
+
+#[derive(Default)]
+struct AsciiState {
+    /// Name and `solid` line of the solid currently being read
+    solid: Option<(String, SourceLocation)>,
+    seen_solid: bool,
+    /// Whether the remaining lines of a skipped facet are being passed over
+    skipping: bool,
+    /// Whether the facets outside of a solid since the last marker were reported
+    outside_reported: bool,
+}
+
+impl StlStreamReader<tokio::fs::File> {
+    /// Open a file for streaming, its length is used to detect binary files
+    pub async fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let file = tokio::fs::File::open(path)
+            .await
+            .map_err(|error| CadStreamError::io(path, error))?;
+        let len = file
+            .metadata()
+            .await
+            .map_err(|error| CadStreamError::io(path, error))?
+            .len();
+
+        Self::new(file, Some(len)).await
+    }
+}
+
+impl<R: AsyncRead + Unpin> StlStreamReader<R> {
+    /// Start reading from `reader`, `total_len` improves binary/ASCII detection
+    pub async fn new(mut reader: R, total_len: Option<u64>) -> Result<Self> {
+        let mut prefix = Vec::with_capacity(ENCODING_PROBE_LEN);
+        (&mut reader)
+            .take(ENCODING_PROBE_LEN as u64)
+            .read_to_end(&mut prefix)
+            .await?;
+
+        let encoding = StlParser::detect_encoding_from_prefix(&prefix, total_len);
+        let mut reader = BufReader::new(Probed {
+            prefix,
+            position: 0,
+            inner: reader,
+        });
+
+        let state = match encoding {
+            StlEncoding::Binary => {
+                let mut header = [0u8; 84];
+                reader
+                    .read_exact(&mut header)
+                    .await
+                    .map_err(|_| CadStreamError::truncated("Binary STL too small"))?;
+                let facet_count =
+                    u32::from_le_bytes([header[80], header[81], header[82], header[83]]);
+                ReadState::Binary {
+                    remaining: facet_count as usize,
+                }
+            }
+            StlEncoding::Ascii => ReadState::Ascii(AsciiState::default()),
+        };
+
+        Ok(Self {
+            reader,
+            encoding,
+            batch_size: DEFAULT_BATCH_SIZE,
+            options: ParseOptions::default(),
+            report: ParseReport::default(),
+            triangles_read: 0,
+            bytes_read: match encoding {
+                StlEncoding::Binary => 84,
+                StlEncoding::Ascii => 0,
+            },
+            line: 0,
+            pending: VecDeque::new(),
+            state,
+        })
+    }
+
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Parse with `options`, in lenient mode malformed facets and solids are skipped
+    pub fn with_options(mut self, options: ParseOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn encoding(&self) -> StlEncoding {
+        self.encoding
+    }
+
+    /// Problems skipped so far in lenient mode
+    pub fn report(&self) -> &ParseReport {
+        &self.report
+    }
+
+    /// Read the next batch, `None` once the file is exhausted
+    pub async fn next_batch(&mut self) -> Result<Option<TriangleBatch>> {
+        let first_triangle = self.triangles_read;
+        let result = match self.state {
+            ReadState::Binary { .. } => self.next_binary_batch().await,
+            ReadState::Ascii(_) => self.next_ascii_batch().await,
+            ReadState::Done => Ok(None),
+        };
+
+        // Stop after the first error, the stream position is no longer meaningful
+        let batch = result.inspect_err(|_| self.state = ReadState::Done)?;
+
+        Ok(batch.map(|(triangles, solid)| {
+            self.triangles_read += triangles.len();
+            TriangleBatch {
+                first_triangle,
+                triangles,
+                solid,
+            }
+        }))
+    }
+
+    /// Turn the reader into an async `Stream` of batches
+    pub fn into_stream(self) -> impl Stream<Item = Result<TriangleBatch>> {
+        stream::try_unfold(self, |mut reader| async move {
+            Ok(reader.next_batch().await?.map(|batch| (batch, reader)))
+        })
+    }
+
+    /// Read every batch into a `CadModel`, ASCII solids become parts
+    pub async fn into_model(self, name: String) -> Result<CadModel> {
+        Ok(self.into_model_with_report(name).await?.0)
+    }
+
+    /// Like [`StlStreamReader::into_model`], also returning what was skipped in
+    /// lenient mode
+    pub async fn into_model_with_report(mut self, name: String) -> Result<(CadModel, ParseReport)> {
+        let mut triangles = Vec::new();
+        let mut parts: Vec<MeshPart> = Vec::new();
+
+        while let Some(batch) = self.next_batch().await? {
+            let range = triangles.len()..triangles.len() + batch.triangles.len();
+            triangles.extend(batch.triangles);
+
+            if let Some(solid) = batch.solid {
+                // Consecutive batches of the same solid extend its part
+                match parts.last_mut() {
+                    Some(part) if part.name == solid && part.triangles.end == range.start => {
+                        part.triangles.end = range.end;
+                    }
+                    _ => parts.push(MeshPart {
+                        name: solid,
+                        triangles: range,
+                    }),
+                }
+            }
+        }
+
+        let mut model = CadModel::new(name, triangles);
+        model.parts = parts;
+        model.precision_info.file_size_bytes = self.bytes_read;
+        Ok((model, self.report))
+    }
+
+    async fn next_binary_batch(&mut self) -> Result<Option<(Vec<Triangle>, Option<String>)>> {
+        let ReadState::Binary { remaining } = &mut self.state else {
+            unreachable!()
+        };
+
+        if *remaining == 0 {
+            self.state = ReadState::Done;
+            return Ok(None);
+        }
+
+        let count = (*remaining).min(self.batch_size);
+        *remaining -= count;
+
+        let len = count * BINARY_RECORD_LEN;
+        let mut records = Vec::with_capacity(len);
+        (&mut self.reader)
+            .take(len as u64)
+            .read_to_end(&mut records)
+            .await?;
+        self.bytes_read += records.len();
+
+        if records.len() < len {
+            // In lenient mode the complete facets are kept, like `StlParser` does
+            let complete = records.len() / BINARY_RECORD_LEN;
+            self.report.record(
+                self.options.mode,
+                CadStreamError::truncated("Binary STL data truncated").at(
+                    SourceLocation::ByteOffset(
+                        84 + (self.triangles_read + complete) * BINARY_RECORD_LEN,
+                    ),
+                ),
+            )?;
+            records.truncate(complete * BINARY_RECORD_LEN);
+            *remaining = 0;
+            if complete == 0 {
+                self.state = ReadState::Done;
+                return Ok(None);
+            }
+        }
+
+        let triangles = records
+            .chunks_exact(BINARY_RECORD_LEN)
+            .map(|record| {
+                let vec3 = |offset: usize| {
+                    let f = |i: usize| {
+                        let start = offset + i * 4;
+                        f32::from_le_bytes(record[start..start + 4].try_into().unwrap())
+                    };
+                    Vec3::new(f(0), f(1), f(2))
+                };
+                Triangle {
+                    normal: vec3(0),
+                    vertices: [vec3(12), vec3(24), vec3(36)],
+                }
+            })
+            .collect();
+
+        Ok(Some((triangles, None)))
+    }
+
+    async fn next_ascii_batch(&mut self) -> Result<Option<(Vec<Triangle>, Option<String>)>> {
+        let ReadState::Ascii(mut ascii) = std::mem::replace(&mut self.state, ReadState::Done)
+        else {
+            unreachable!()
+        };
+
+        let batch = self.read_ascii_batch(&mut ascii).await?;
+        if batch.is_some() {
+            self.state = ReadState::Ascii(ascii);
+        }
+        Ok(batch)
+    }
+
+    /// Read facets until the batch is full, a solid ends or the stream does
+    ///
+    /// Reports the same problems as `StlParser::parse_data_with_options`.
+    async fn read_ascii_batch(
+        &mut self,
+        ascii: &mut AsciiState,
+    ) -> Result<Option<(Vec<Triangle>, Option<String>)>> {
+        let parser = StlParser::new();
+        let mode = self.options.mode;
+        let mut triangles = Vec::new();
+
+        while triangles.len() < self.batch_size {
+            let Some(line) = self.next_line().await? else {
+                if !ascii.seen_solid {
+                    return Err(CadStreamError::malformed(
+                        "Invalid ASCII STL: missing 'solid' header",
+                    ));
+                }
+                let solid = match ascii.solid.take() {
+                    Some((name, location)) => {
+                        self.report.record(
+                            mode,
+                            CadStreamError::malformed("'solid' is never closed by 'endsolid'")
+                                .at(location),
+                        )?;
+                        Some(name)
+                    }
+                    None => None,
+                };
+                return Ok((!triangles.is_empty()).then_some((triangles, solid)));
+            };
+
+            if !ascii.seen_solid && !line.text.starts_with("solid") {
+                return Err(CadStreamError::malformed(
+                    "Invalid ASCII STL: missing 'solid' header",
+                ));
+            }
+
+            if line.text.starts_with("endsolid") {
+                ascii.skipping = false;
+                ascii.outside_reported = false;
+                match ascii.solid.take() {
+                    Some((name, _)) if !triangles.is_empty() => {
+                        return Ok(Some((triangles, Some(name))));
+                    }
+                    Some(_) => {}
+                    None => self.report.record(
+                        mode,
+                        CadStreamError::malformed("'endsolid' without 'solid'")
+                            .at(line.location(0)),
+                    )?,
+                }
+            } else if line.text.starts_with("solid") {
+                // Batches never span solids, finish this one and read the line again
+                if !triangles.is_empty() {
+                    let solid = ascii.solid.as_ref().map(|(name, _)| name.clone());
+                    self.pending.push_front(line);
+                    return Ok(Some((triangles, solid)));
+                }
+
+                if ascii.solid.is_some() {
+                    self.report.record(
+                        mode,
+                        CadStreamError::malformed("'solid' before 'endsolid'").at(line.location(0)),
+                    )?;
+                }
+                ascii.solid = Some((parser.solid_name(&line.text), line.location(0)));
+                ascii.seen_solid = true;
+                ascii.skipping = false;
+                ascii.outside_reported = false;
+            } else if line.text.starts_with("facet") {
+                ascii.skipping = false;
+
+                self.read_ahead(FACET_LINES).await?;
+                let mut lines = std::mem::take(&mut self.pending).into_iter().peekable();
+                let facet = parser.parse_ascii_facet(&line, &mut lines);
+                // Lines the facet didn't use, e.g. the one that broke it, are read next
+                self.pending = lines.collect();
+
+                match facet {
+                    Ok(triangle) => {
+                        if ascii.solid.is_none() && !ascii.outside_reported {
+                            self.report.record(
+                                mode,
+                                CadStreamError::malformed("Facet outside of a solid")
+                                    .at(line.location(0)),
+                            )?;
+                            ascii.outside_reported = true;
+                        }
+                        triangles.push(triangle);
+                    }
+                    Err(error) => {
+                        self.report.record(mode, error)?;
+                        ascii.skipping = true;
+                    }
+                }
+            } else if !ascii.skipping {
+                self.report.record(
+                    mode,
+                    CadStreamError::malformed(format!("Unexpected line: {}", line.text))
+                        .at(line.location(0)),
+                )?;
+            }
+        }
+
+        let solid = ascii.solid.as_ref().map(|(name, _)| name.clone());
+        Ok(Some((triangles, solid)))
+    }
+
+    /// Next non-blank line, `None` at the end of the stream
+    async fn next_line(&mut self) -> Result<Option<AsciiLine<'static>>> {
+        match self.pending.pop_front() {
+            Some(line) => Ok(Some(line)),
+            None => self.read_line().await,
+        }
+    }
+
+    /// Buffer up to `count` lines ahead, fewer at the end of the stream
+    async fn read_ahead(&mut self, count: usize) -> Result<()> {
+        while self.pending.len() < count {
+            let Some(line) = self.read_line().await? else {
+                break;
+            };
+            self.pending.push_back(line);
+        }
+        Ok(())
+    }
+
+    async fn read_line(&mut self) -> Result<Option<AsciiLine<'static>>> {
+        let max_len = self.options.line_length_limit();
+        let mut buffer = Vec::new();
+        loop {
+            buffer.clear();
+            // One byte over the limit tells a line that is too long from one that fits
+            let len = (&mut self.reader)
+                .take(max_len as u64 + 1)
+                .read_until(b'\n', &mut buffer)
+                .await?;
+            if len == 0 {
+                return Ok(None);
+            }
+            self.bytes_read += len;
+            self.line += 1;
+
+            let content_len = buffer.len() - usize::from(buffer.ends_with(b"\n"));
+            if content_len > max_len {
+                return Err(CadStreamError::malformed(format!(
+                    "Line is longer than {} bytes",
+                    max_len
+                ))
+                .at(SourceLocation::Line {
+                    line: self.line,
+                    column: max_len + 1,
+                }));
+            }
+
+            if let Some(line) = AsciiLine::new(self.line, &buffer) {
+                return Ok(Some(line.into_owned()));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsers::ParseMode;
+    use crate::stl_writer::StlWriter;
+    use futures::TryStreamExt;
+
+    fn sample_model(triangle_count: usize) -> CadModel {
+        let triangles = (0..triangle_count)
+            .map(|i| Triangle {
+                vertices: [
+                    Vec3::new(i as f32, 0.0, 0.0),
+                    Vec3::new(i as f32, 1.0, 0.0),
+                    Vec3::new(i as f32, 0.0, 1.0),
+                ],
+                normal: Vec3::X,
+            })
+            .collect();
+        let mut model = CadModel::new("stream.stl".to_string(), triangles);
+        model.parts = vec![
+            MeshPart {
+                name: "first".to_string(),
+                triangles: 0..3,
+            },
+            MeshPart {
+                name: "second".to_string(),
+                triangles: 3..triangle_count,
+            },
+        ];
+        model
+    }
+
+    #[tokio::test]
+    async fn test_stream_batches_match_full_parse() {
+        let model = sample_model(10);
+
+        for encoding in [StlEncoding::Binary, StlEncoding::Ascii] {
+            let data = StlWriter::new().to_bytes(&model, encoding).unwrap();
+            let reader = StlStreamReader::new(data.as_slice(), Some(data.len() as u64))
+                .await
+                .unwrap()
+                .with_batch_size(4);
+            assert_eq!(reader.encoding(), encoding);
+
+            let batches: Vec<TriangleBatch> = reader.into_stream().try_collect().await.unwrap();
+            let sizes: Vec<usize> = batches.iter().map(|b| b.triangles.len()).collect();
+            let expected = match encoding {
+                StlEncoding::Binary => vec![4, 4, 2],
+                StlEncoding::Ascii => vec![3, 4, 3],
+            };
+            assert_eq!(sizes, expected);
+            assert_eq!(batches[2].first_triangle, 10 - expected[2]);
+
+            let streamed = StlStreamReader::new(data.as_slice(), Some(data.len() as u64))
+                .await
+                .unwrap()
+                .with_batch_size(4)
+                .into_model("stream.stl".to_string())
+                .await
+                .unwrap();
+            let parsed = StlParser::new()
+                .parse_data(&data, "stream.stl".to_string())
+                .unwrap();
+            assert_eq!(streamed.triangles.len(), parsed.triangles.len());
+            assert_eq!(streamed.parts, parsed.parts);
+            assert_eq!(streamed.precision_info.file_size_bytes, data.len());
+            for (a, b) in streamed.triangles.iter().zip(&parsed.triangles) {
+                assert_eq!(a.vertices, b.vertices);
+                assert_eq!(a.normal, b.normal);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_truncated_stream_is_an_error() {
+        let data = StlWriter::new()
+            .to_bytes(&sample_model(5), StlEncoding::Binary)
+            .unwrap();
+        let truncated = &data[..data.len() - 10];
+
+        let result = StlStreamReader::new(truncated, None)
+            .await
+            .unwrap()
+            .into_model("truncated.stl".to_string())
+            .await;
+        assert!(matches!(
+            result.unwrap_err(),
+            CadStreamError::Truncated {
+                location: Some(SourceLocation::ByteOffset(284)),
+                ..
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_long_lines_are_an_error() {
+        let data = format!(
+            "solid long\nfacet normal 0 0 1 {}\nendsolid long\n",
+            "0".repeat(100)
+        );
+        let options = ParseOptions {
+            mode: ParseMode::Lenient,
+            max_line_length: Some(64),
+            ..Default::default()
+        };
+
+        let error = StlStreamReader::new(data.as_bytes(), None)
+            .await
+            .unwrap()
+            .with_options(options)
+            .into_model("long.stl".to_string())
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.location(),
+            Some(SourceLocation::Line {
+                line: 2,
+                column: 65
+            })
+        );
+
+        // A line of exactly the limit still fits
+        let options = ParseOptions {
+            max_line_length: Some(13),
+            ..Default::default()
+        };
+        let model = StlStreamReader::new(b"solid long\nendsolid long\n".as_slice(), None)
+            .await
+            .unwrap()
+            .with_options(options)
+            .into_model("long.stl".to_string())
+            .await
+            .unwrap();
+        assert!(model.triangles.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_stream_reports_the_same_problems_as_full_parse() {
+        let data = b"solid broken
+facet normal 0 0 1
+outer loop
+vertex 0 0 0
+vertex 1 0 0
+vertex 0 1 0
+endloop
+endfacet
+facet normal 0 0 1
+outer loop
+vertex 0 0 0
+vertex 1 0 0
+vertex 0 1 0
+endfacet
+facet normal 0 0 1
+outer loop
+vertex 0 0 1
+vertex 1 0 1
+vertex 0 1 1
+endloop
+endfacet
+endsolid broken
+";
+        let stream = |options| async move {
+            StlStreamReader::new(data.as_slice(), None)
+                .await
+                .unwrap()
+                .with_batch_size(1)
+                .with_options(options)
+                .into_model_with_report("broken.stl".to_string())
+                .await
+        };
+        let parser = StlParser::new();
+
+        // A missing 'endloop' is located at the line found instead
+        let strict = ParseOptions::default();
+        let error = stream(strict).await.unwrap_err();
+        assert_eq!(
+            error.location(),
+            Some(SourceLocation::Line {
+                line: 14,
+                column: 1
+            })
+        );
+        let parsed = parser
+            .parse_data_with_options(data, "broken.stl".to_string(), &strict)
+            .unwrap_err();
+        assert_eq!(error.to_string(), parsed.to_string());
+
+        let lenient = ParseOptions {
+            mode: ParseMode::Lenient,
+            ..Default::default()
+        };
+        let (streamed, report) = stream(lenient).await.unwrap();
+        let (parsed, parsed_report) = parser
+            .parse_data_with_options(data, "broken.stl".to_string(), &lenient)
+            .unwrap();
+        assert_eq!(streamed.triangles.len(), 2);
+        assert_eq!(streamed.parts, parsed.parts);
+        let messages = |report: &ParseReport| -> Vec<String> {
+            report.warnings.iter().map(|w| w.to_string()).collect()
+        };
+        assert_eq!(messages(&report), messages(&parsed_report));
+    }
+}
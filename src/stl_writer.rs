@@ -0,0 +1,307 @@
+use glam::Vec3;
+use std::io::Write;
+use std::path::Path;
+
+use crate::cad_data::{CadModel, Rgba};
+use crate::error::{CadStreamError, Result};
+use crate::stl_parser::StlEncoding;
+
+/// Size of the fixed binary STL header
+pub const BINARY_HEADER_LEN: usize = 80;
+
+/// Serialises a `CadModel` to binary or ASCII STL
+///
+/// With the default settings the output parses back into bit-identical triangles
+/// with `StlParser::parse_data`. Binary output stores the first corner color of each
+/// facet in VisCAM's 15-bit format, so colors come back quantized to 5 bits per channel.
+#[derive(Debug, Clone)]
+pub struct StlWriter {
+    /// Binary header, zero padded to 80 bytes
+    pub header: Vec<u8>,
+    /// Digits after the decimal point in ASCII output, `None` writes the shortest
+    /// representation that round-trips exactly
+    pub precision: Option<usize>,
+    /// Name after `solid`/`endsolid` in ASCII output for models without parts,
+    /// defaults to the model name without its extension
+    pub solid_name: Option<String>,
+}
+
+impl Default for StlWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StlWriter {
+    pub fn new() -> Self {
+        Self {
+            header: b"Binary STL written by cadstream".to_vec(),
+            precision: None,
+            solid_name: None,
+        }
+    }
+
+    pub async fn write_file<P: AsRef<Path>>(
+        &self,
+        model: &CadModel,
+        path: P,
+        encoding: StlEncoding,
+    ) -> Result<()> {
+        let data = self.to_bytes(model, encoding)?;
+
+        tokio::fs::write(&path, data)
+            .await
+            .map_err(|error| CadStreamError::io(path.as_ref(), error))
+    }
+
+    pub fn to_bytes(&self, model: &CadModel, encoding: StlEncoding) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        match encoding {
+            StlEncoding::Binary => self.write_binary(model, &mut data)?,
+            StlEncoding::Ascii => self.write_ascii(model, &mut data)?,
+        }
+        Ok(data)
+    }
+
+    pub fn write_binary<W: Write>(&self, model: &CadModel, writer: &mut W) -> Result<()> {
+        if self.header.len() > BINARY_HEADER_LEN {
+            return Err(CadStreamError::Export(format!(
+                "STL header is {} bytes, at most {} are allowed",
+                self.header.len(),
+                BINARY_HEADER_LEN
+            )));
+        }
+
+        let triangle_count = u32::try_from(model.triangles.len())
+            .map_err(|_| CadStreamError::Export("Too many triangles for binary STL".to_string()))?;
+
+        let mut header = [0u8; BINARY_HEADER_LEN];
+        header[..self.header.len()].copy_from_slice(&self.header);
+        writer.write_all(&header)?;
+        writer.write_all(&triangle_count.to_le_bytes())?;
+
+        for (index, triangle) in model.triangles.iter().enumerate() {
+            self.write_vec3_le(writer, triangle.normal)?;
+            for vertex in &triangle.vertices {
+                self.write_vec3_le(writer, *vertex)?;
+            }
+
+            // Attribute bytes, holding a VisCAM color for colored models
+            let attribute = model
+                .vertex_colors
+                .as_ref()
+                .map_or(0, |colors| self.pack_viscam_color(colors[index][0]));
+            writer.write_all(&attribute.to_le_bytes())?;
+        }
+
+        Ok(())
+    }
+
+    /// Write ASCII STL, one `solid` block per part when the model has parts
+    pub fn write_ascii<W: Write>(&self, model: &CadModel, writer: &mut W) -> Result<()> {
+        let default_name = match &self.solid_name {
+            Some(name) => name.clone(),
+            None => Path::new(&model.name)
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .unwrap_or(&model.name)
+                .to_string(),
+        };
+
+        // Triangles outside every part go into solids with the default name, so
+        // nothing is lost compared to binary output
+        for part in model.covering_parts() {
+            let solid_name = if part.name.is_empty() {
+                default_name.clone()
+            } else {
+                part.name
+            };
+
+            if solid_name.contains(['\n', '\r']) {
+                return Err(CadStreamError::Export(
+                    "Solid name must not contain line breaks".to_string(),
+                ));
+            }
+
+            writeln!(writer, "solid {}", solid_name)?;
+
+            for triangle in &model.triangles[part.triangles] {
+                writeln!(writer, "facet normal {}", self.format_vec3(triangle.normal))?;
+                writeln!(writer, "  outer loop")?;
+                for vertex in &triangle.vertices {
+                    writeln!(writer, "    vertex {}", self.format_vec3(*vertex))?;
+                }
+                writeln!(writer, "  endloop")?;
+                writeln!(writer, "endfacet")?;
+            }
+
+            writeln!(writer, "endsolid {}", solid_name)?;
+        }
+
+        Ok(())
+    }
+
+    fn format_vec3(&self, v: Vec3) -> String {
+        // `Display` for f32 prints the shortest string that parses back to the same bits
+        match self.precision {
+            Some(precision) => format!("{:.p$} {:.p$} {:.p$}", v.x, v.y, v.z, p = precision),
+            None => format!("{} {} {}", v.x, v.y, v.z),
+        }
+    }
+
+    /// 15-bit RGB with blue in the low bits and bit 15 marking the color as valid
+    fn pack_viscam_color(&self, [r, g, b, _]: Rgba) -> u16 {
+        let channel = |value: u8| (value >> 3) as u16;
+        0x8000 | (channel(r) << 10) | (channel(g) << 5) | channel(b)
+    }
+
+    fn write_vec3_le<W: Write>(&self, writer: &mut W, v: Vec3) -> Result<()> {
+        for component in v.to_array() {
+            writer.write_all(&component.to_le_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cad_data::{MeshPart, Triangle};
+    use crate::stl_parser::StlParser;
+
+    fn sample_model() -> CadModel {
+        let triangles = vec![
+            Triangle {
+                vertices: [
+                    Vec3::new(0.1, -2.5e-7, 3.4028235e38),
+                    Vec3::new(1.0 / 3.0, 0.0, -0.0),
+                    Vec3::new(123456.79, 1e-30, 7.0),
+                ],
+                normal: Vec3::new(0.0, 0.0, 1.0),
+            },
+            Triangle {
+                vertices: [Vec3::ZERO, Vec3::X, Vec3::Y],
+                normal: Vec3::new(0.57735026, 0.57735026, -0.57735026),
+            },
+        ];
+        CadModel::new("sample.stl".to_string(), triangles)
+    }
+
+    fn assert_bit_identical(a: &CadModel, b: &CadModel) {
+        assert_eq!(a.triangles.len(), b.triangles.len());
+        for (ta, tb) in a.triangles.iter().zip(&b.triangles) {
+            for (va, vb) in ta
+                .vertices
+                .iter()
+                .chain([&ta.normal])
+                .zip(tb.vertices.iter().chain([&tb.normal]))
+            {
+                assert_eq!(
+                    va.to_array().map(f32::to_bits),
+                    vb.to_array().map(f32::to_bits)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_binary_and_ascii_round_trip_exactly() {
+        let model = sample_model();
+        let writer = StlWriter::new();
+        let parser = StlParser::new();
+
+        let binary = writer.to_bytes(&model, StlEncoding::Binary).unwrap();
+        assert_eq!(binary.len(), 84 + 50 * model.triangles.len());
+        assert!(binary.starts_with(b"Binary STL written by cadstream\0"));
+        let parsed = parser
+            .parse_data(&binary, "sample.stl".to_string())
+            .unwrap();
+        assert_bit_identical(&model, &parsed);
+
+        let ascii = writer.to_bytes(&model, StlEncoding::Ascii).unwrap();
+        assert!(ascii.starts_with(b"solid sample\n"));
+        let parsed = parser.parse_data(&ascii, "sample.stl".to_string()).unwrap();
+        assert_bit_identical(&model, &parsed);
+        assert!(parsed.vertex_colors.is_none());
+    }
+
+    #[test]
+    fn test_empty_model_round_trips() {
+        let model = CadModel::new("empty.stl".to_string(), Vec::new());
+        let parser = StlParser::new();
+
+        for encoding in [StlEncoding::Binary, StlEncoding::Ascii] {
+            let bytes = StlWriter::new().to_bytes(&model, encoding).unwrap();
+            let parsed = parser.parse_data(&bytes, "empty.stl".to_string()).unwrap();
+            assert!(parsed.triangles.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_binary_colors_round_trip_quantized() {
+        let mut model = sample_model();
+        model.vertex_colors = Some(vec![[[255, 0, 8, 255]; 3], [[0, 255, 0, 255]; 3]]);
+
+        let binary = StlWriter::new()
+            .to_bytes(&model, StlEncoding::Binary)
+            .unwrap();
+        let parsed = StlParser::new()
+            .parse_data(&binary, "sample.stl".to_string())
+            .unwrap();
+
+        assert_eq!(parsed.vertex_colors, model.vertex_colors);
+    }
+
+    #[test]
+    fn test_header_and_precision_options() {
+        let writer = StlWriter {
+            header: vec![b'x'; 81],
+            precision: Some(2),
+            solid_name: Some("bracket".to_string()),
+        };
+        let model = sample_model();
+
+        assert!(writer.to_bytes(&model, StlEncoding::Binary).is_err());
+
+        let ascii =
+            String::from_utf8(writer.to_bytes(&model, StlEncoding::Ascii).unwrap()).unwrap();
+        assert!(ascii.contains("vertex 0.33 0.00 -0.00"));
+        assert!(ascii.ends_with("endsolid bracket\n"));
+    }
+
+    #[test]
+    fn test_parts_are_written_as_separate_solids() {
+        let mut model = sample_model();
+        model.parts = vec![
+            MeshPart {
+                name: "first".to_string(),
+                triangles: 0..1,
+            },
+            MeshPart {
+                name: "second".to_string(),
+                triangles: 1..2,
+            },
+        ];
+
+        let ascii = StlWriter::new()
+            .to_bytes(&model, StlEncoding::Ascii)
+            .unwrap();
+        let parsed = StlParser::new()
+            .parse_data(&ascii, "sample.stl".to_string())
+            .unwrap();
+
+        assert_eq!(parsed.parts, model.parts);
+        assert_bit_identical(&model, &parsed);
+
+        // A triangle outside every part still gets written
+        model.parts.truncate(1);
+        let ascii = StlWriter::new()
+            .to_bytes(&model, StlEncoding::Ascii)
+            .unwrap();
+        let parsed = StlParser::new()
+            .parse_data(&ascii, "sample.stl".to_string())
+            .unwrap();
+        assert_bit_identical(&model, &parsed);
+        assert_eq!(parsed.parts[1].name, "sample");
+    }
+}
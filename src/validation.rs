@@ -0,0 +1,185 @@
+use glam::Vec3;
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::cad_data::{CadModel, DEFAULT_WELD_EPSILON};
+
+/// Minimum dot product between a stored facet normal and the winding normal
+/// before the facet is reported as flipped
+const FLIPPED_NORMAL_THRESHOLD: f32 = -0.5;
+
+/// Mesh defects found by [`validate`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ValidationReport {
+    pub triangle_count: usize,
+    /// Triangles with any NaN or infinite coordinate
+    pub non_finite_triangles: usize,
+    /// Triangles with (near) zero area relative to their edge lengths
+    pub degenerate_triangles: usize,
+    /// Triangles whose stored normal points against their vertex winding
+    pub flipped_normals: usize,
+    /// Edges used by only one triangle
+    pub open_edges: usize,
+    /// Edges shared by more than two triangles
+    pub non_manifold_edges: usize,
+    /// Edges shared by two triangles that traverse them in the same direction
+    pub misoriented_edges: usize,
+}
+
+impl ValidationReport {
+    /// Whether the mesh has defects that break downstream tools
+    pub fn has_errors(&self) -> bool {
+        self.non_finite_triangles > 0 || self.degenerate_triangles > 0
+    }
+
+    /// Whether the mesh is a closed, consistently oriented 2-manifold
+    pub fn is_watertight(&self) -> bool {
+        self.open_edges == 0 && self.non_manifold_edges == 0 && self.misoriented_edges == 0
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Triangles:          {}", self.triangle_count)?;
+        writeln!(f, "Non-finite:         {}", self.non_finite_triangles)?;
+        writeln!(f, "Degenerate:         {}", self.degenerate_triangles)?;
+        writeln!(f, "Flipped normals:    {}", self.flipped_normals)?;
+        writeln!(f, "Open edges:         {}", self.open_edges)?;
+        writeln!(f, "Non-manifold edges: {}", self.non_manifold_edges)?;
+        write!(f, "Misoriented edges:  {}", self.misoriented_edges)
+    }
+}
+
+/// Check a model for degenerate or non-finite triangles, flipped normals and
+/// open, non-manifold or misoriented edges
+pub fn validate(model: &CadModel) -> ValidationReport {
+    let mut report = ValidationReport {
+        triangle_count: model.triangles.len(),
+        ..Default::default()
+    };
+
+    // Edge usage is counted on welded positions so shared corners line up
+    let mut vertex_ids: HashMap<[i64; 3], u32> = HashMap::new();
+    // Uses of each edge, and how many of them run from the lower to the higher id
+    let mut edge_usage: HashMap<(u32, u32), (usize, usize)> = HashMap::new();
+
+    for triangle in &model.triangles {
+        if !triangle.vertices.iter().all(|v| v.is_finite()) {
+            report.non_finite_triangles += 1;
+            continue;
+        }
+
+        let [a, b, c] = triangle.vertices;
+        let winding_normal = (b - a).cross(c - a);
+        // Relative to the edges, so tiny parts aren't degenerate and slivers of
+        // large ones are
+        if winding_normal.length() <= f32::EPSILON * (b - a).length() * (c - a).length() {
+            report.degenerate_triangles += 1;
+            continue;
+        }
+
+        if triangle.normal != Vec3::ZERO
+            && triangle
+                .normal
+                .normalize_or_zero()
+                .dot(winding_normal.normalize())
+                < FLIPPED_NORMAL_THRESHOLD
+        {
+            report.flipped_normals += 1;
+        }
+
+        let face = triangle.vertices.map(|v| {
+            let key = (v / DEFAULT_WELD_EPSILON).round().as_i64vec3().to_array();
+            let next_id = vertex_ids.len() as u32;
+            *vertex_ids.entry(key).or_insert(next_id)
+        });
+        for i in 0..3 {
+            let (a, b) = (face[i], face[(i + 1) % 3]);
+            if a != b {
+                let usage = edge_usage.entry((a.min(b), a.max(b))).or_default();
+                usage.0 += 1;
+                usage.1 += usize::from(a < b);
+            }
+        }
+    }
+
+    for &(count, forward) in edge_usage.values() {
+        match count {
+            1 => report.open_edges += 1,
+            2 if forward != 1 => report.misoriented_edges += 1,
+            2 => {}
+            _ => report.non_manifold_edges += 1,
+        }
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cad_data::Triangle;
+
+    fn tetrahedron() -> Vec<Triangle> {
+        let p = [
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+        ];
+        [[0, 2, 1], [0, 1, 3], [0, 3, 2], [1, 2, 3]]
+            .iter()
+            .map(|&[a, b, c]| {
+                let vertices = [p[a], p[b], p[c]];
+                let normal = (vertices[1] - vertices[0])
+                    .cross(vertices[2] - vertices[0])
+                    .normalize();
+                Triangle { vertices, normal }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_validate_closed_and_broken_meshes() {
+        let closed = CadModel::new("tetra".to_string(), tetrahedron());
+        let report = validate(&closed);
+        assert!(!report.has_errors());
+        assert!(report.is_watertight());
+        assert_eq!(report.flipped_normals, 0);
+
+        // Size alone doesn't make a triangle degenerate
+        let mut tiny = tetrahedron();
+        for triangle in &mut tiny {
+            triangle.vertices = triangle.vertices.map(|v| v * 1e-4);
+        }
+        assert!(!validate(&CadModel::new("tiny".to_string(), tiny)).has_errors());
+        let sliver = Triangle {
+            vertices: [Vec3::ZERO, Vec3::X * 1000.0, Vec3::new(2000.0, 1e-4, 0.0)],
+            normal: Vec3::Z,
+        };
+        let report = validate(&CadModel::new("sliver".to_string(), vec![sliver]));
+        assert_eq!(report.degenerate_triangles, 1);
+
+        // A face wound the wrong way still closes the mesh, but not consistently
+        let mut inverted = tetrahedron();
+        inverted[3].vertices.swap(1, 2);
+        let report = validate(&CadModel::new("inverted".to_string(), inverted));
+        assert_eq!(report.open_edges, 0);
+        assert_eq!(report.misoriented_edges, 3);
+        assert!(!report.is_watertight());
+
+        let mut triangles = tetrahedron();
+        triangles.pop();
+        triangles[0].normal = -triangles[0].normal;
+        triangles.push(Triangle {
+            vertices: [Vec3::ZERO, Vec3::X, Vec3::X * 2.0],
+            normal: Vec3::Z,
+        });
+        let report = validate(&CadModel::new("broken".to_string(), triangles));
+        assert!(report.has_errors());
+        assert_eq!(report.degenerate_triangles, 1);
+        assert_eq!(report.flipped_normals, 1);
+        assert_eq!(report.open_edges, 3);
+        assert!(!report.is_watertight());
+    }
+}
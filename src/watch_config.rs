@@ -0,0 +1,227 @@
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::archive::ARCHIVE_EXTENSIONS;
+use crate::error::{CadStreamError, Result};
+use crate::parsers::{ParseOptions, ParserRegistry};
+
+/// What the `FileWatcher` watches and which files it loads
+#[derive(Debug, Clone)]
+pub struct WatchConfig {
+    /// Directories to watch, relative paths are resolved against the working directory
+    pub roots: Vec<PathBuf>,
+    /// Also watch and scan subdirectories of every root
+    pub recursive: bool,
+    /// Glob patterns a file must match to be loaded, empty means every supported file
+    pub include: Vec<String>,
+    /// Glob patterns that exclude a file even if it matches `include`
+    pub exclude: Vec<String>,
+    /// Files larger than this many bytes are skipped, and compressed files or archives
+    /// that unpack to more fail to load
+    pub max_file_size: Option<u64>,
+    /// Parsers used for loaded files, and which one wins for each format
+    pub parsers: ParserRegistry,
+    /// Strict or lenient handling of malformed files
+    pub parse_options: ParseOptions,
+    pub debounce: DebounceConfig,
+}
+
+impl Default for WatchConfig {
+    fn default() -> Self {
+        Self {
+            roots: vec![PathBuf::from(".")],
+            recursive: false,
+            include: Vec::new(),
+            exclude: Vec::new(),
+            max_file_size: None,
+            parsers: ParserRegistry::new(),
+            parse_options: ParseOptions::default(),
+            debounce: DebounceConfig::default(),
+        }
+    }
+}
+
+/// Controls how long the watcher waits for a file to finish being written
+#[derive(Debug, Clone)]
+pub struct DebounceConfig {
+    /// Size and modification time must stay unchanged for this long before parsing
+    pub quiet_period: Duration,
+    /// How often size and modification time are sampled while waiting
+    pub poll_interval: Duration,
+    /// How many times a failed parse is retried after the file changed again
+    pub max_retries: u32,
+}
+
+impl Default for DebounceConfig {
+    fn default() -> Self {
+        Self {
+            quiet_period: Duration::from_millis(500),
+            poll_interval: Duration::from_millis(100),
+            max_retries: 3,
+        }
+    }
+}
+
+/// Compiled form of the path rules in a [`WatchConfig`]
+///
+/// A glob matches a file if it matches the path relative to its root (the absolute
+/// path for files outside every root) or the bare file name, so both `**/tmp/**` and
+/// `*_backup.stl` work.
+#[derive(Debug, Clone)]
+pub struct WatchFilter {
+    roots: Vec<PathBuf>,
+    parsers: ParserRegistry,
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+}
+
+impl WatchFilter {
+    pub fn new(config: &WatchConfig) -> Result<Self> {
+        let current_dir = std::env::current_dir()?;
+        let roots = config
+            .roots
+            .iter()
+            .map(|root| current_dir.join(root).components().collect())
+            .collect();
+
+        let include = if config.include.is_empty() {
+            None
+        } else {
+            Some(Self::build_glob_set(&config.include)?)
+        };
+
+        Ok(Self {
+            roots,
+            parsers: config.parsers.clone(),
+            include,
+            exclude: Self::build_glob_set(&config.exclude)?,
+        })
+    }
+
+    /// Absolute paths of the watched roots
+    pub fn roots(&self) -> &[PathBuf] {
+        &self.roots
+    }
+
+    /// Whether `path` is a supported CAD file that passes the include/exclude rules
+    ///
+    /// Files in hidden directories such as `.git` are skipped, and files without an
+    /// extension are only loaded when an include pattern names them.
+    pub fn matches(&self, path: &Path) -> bool {
+        // Match relative to the root so a root below e.g. `/tmp` isn't hit by `**/tmp/**`
+        let relative = self
+            .roots
+            .iter()
+            .find_map(|root| path.strip_prefix(root).ok())
+            .unwrap_or(path);
+
+        let in_hidden_directory = relative
+            .parent()
+            .is_some_and(|parent| parent.iter().any(is_hidden));
+        if in_hidden_directory {
+            return false;
+        }
+
+        let mut candidates = vec![relative];
+        if let Some(file_name) = path.file_name() {
+            candidates.push(Path::new(file_name));
+        }
+
+        let supported = match path.extension() {
+            Some(_) => self.is_supported_file(path),
+            // Their format is detected from the content, which means reading every
+            // `Makefile` or `LICENSE` unless asked for explicitly
+            None => self.include.is_some(),
+        };
+        let included = match &self.include {
+            Some(include) => candidates.iter().any(|c| include.is_match(c)),
+            None => true,
+        };
+
+        supported && included && !candidates.iter().any(|c| self.exclude.is_match(c))
+    }
+
+    /// Whether `path` is one of the roots or lies below one
+    pub fn contains(&self, path: &Path) -> bool {
+        self.roots.iter().any(|root| path.starts_with(root))
+    }
+
+    /// Whether the watcher should scan and watch inside `directory`
+    pub fn descends_into(&self, directory: &Path) -> bool {
+        !directory.file_name().is_some_and(is_hidden)
+    }
+
+    /// Files named like a supported format, e.g. `part.stl`, `part.STL.tmp` or
+    /// `part.stl.gz`, and archives
+    fn is_supported_file(&self, path: &Path) -> bool {
+        if self.parsers.format_for_path(path).is_some() {
+            return true;
+        }
+
+        path.extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|extension| {
+                ARCHIVE_EXTENSIONS.contains(&extension.to_lowercase().as_str())
+            })
+    }
+
+    fn build_glob_set(patterns: &[String]) -> Result<GlobSet> {
+        let mut builder = GlobSetBuilder::new();
+        for pattern in patterns {
+            builder.add(
+                Glob::new(pattern).map_err(|source| CadStreamError::InvalidPattern {
+                    pattern: pattern.clone(),
+                    source,
+                })?,
+            );
+        }
+        builder
+            .build()
+            .map_err(|source| CadStreamError::InvalidPattern {
+                pattern: patterns.join(", "),
+                source,
+            })
+    }
+}
+
+/// Dot-directories like `.git` or `.cache`
+fn is_hidden(name: &std::ffi::OsStr) -> bool {
+    name.to_str()
+        .is_some_and(|name| name.starts_with('.') && name != "." && name != "..")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter_include_and_exclude_globs() {
+        let config = WatchConfig {
+            roots: vec![PathBuf::from("/projects")],
+            include: vec!["parts/**".to_string()],
+            exclude: vec!["**/tmp/**".to_string(), "*_backup.stl".to_string()],
+            ..Default::default()
+        };
+        let filter = WatchFilter::new(&config).unwrap();
+
+        assert!(filter.matches(Path::new("/projects/parts/bracket.stl")));
+        assert!(filter.matches(Path::new("/projects/parts/sub/gear.stl")));
+        assert!(!filter.matches(Path::new("/projects/parts/tmp/bracket.stl")));
+        assert!(!filter.matches(Path::new("/projects/parts/bracket_backup.stl")));
+        assert!(!filter.matches(Path::new("/projects/docs/bracket.stl")));
+        assert!(!filter.matches(Path::new("/projects/parts/notes.txt")));
+        assert!(!filter.matches(Path::new("/projects/parts/.cache/bracket.stl")));
+
+        // Extension-less files only when an include pattern asks for them
+        assert!(filter.matches(Path::new("/projects/parts/upload")));
+        let filter = WatchFilter::new(&WatchConfig {
+            roots: vec![PathBuf::from("/projects")],
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(!filter.matches(Path::new("/projects/Makefile")));
+        assert!(!filter.matches(Path::new("/projects/.git/objects/ab/cdef")));
+        assert!(filter.matches(Path::new("/projects/part.stl")));
+    }
+}